@@ -0,0 +1,14 @@
+-- Phase 4: One-time codes for phone/email verification
+
+CREATE TABLE IF NOT EXISTS otp_codes (
+    identifier_hash TEXT PRIMARY KEY,
+    code_hash       TEXT NOT NULL,
+    attempts        INTEGER NOT NULL DEFAULT 0,
+    expires_at      TIMESTAMPTZ NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS otp_codes_expires_idx ON otp_codes (expires_at);
+
+COMMENT ON COLUMN otp_codes.identifier_hash IS 'Hashed phone/email the code was sent to';
+COMMENT ON COLUMN otp_codes.code_hash IS 'SHA256 of identifier_hash:code, never the plaintext code';
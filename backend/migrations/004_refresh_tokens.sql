@@ -0,0 +1,19 @@
+-- Phase 4: Rotating refresh tokens (opaque, stored hashed)
+
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    id              UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    family_id       UUID NOT NULL,
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id       UUID,
+    token_hash      TEXT NOT NULL UNIQUE,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at      TIMESTAMPTZ NOT NULL,
+    used_at         TIMESTAMPTZ,
+    revoked_at      TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_idx ON refresh_tokens (family_id);
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_idx ON refresh_tokens (user_id);
+
+COMMENT ON COLUMN refresh_tokens.family_id IS 'All tokens rotated from the same login share a family';
+COMMENT ON COLUMN refresh_tokens.used_at IS 'Set when rotated; presenting a used token again revokes the family';
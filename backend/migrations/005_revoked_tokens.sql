@@ -0,0 +1,10 @@
+-- Phase 4: Access-token denylist (entries only need to outlive the token itself)
+
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    jti             UUID PRIMARY KEY,
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at      TIMESTAMPTZ NOT NULL,
+    revoked_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS revoked_tokens_expires_idx ON revoked_tokens (expires_at);
@@ -0,0 +1,12 @@
+-- Phase 4: Challenges a device signs with its identity key to get a device-bound token
+
+CREATE TABLE IF NOT EXISTS device_challenges (
+    id              UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id       UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    nonce_b64       TEXT NOT NULL,
+    expires_at      TIMESTAMPTZ NOT NULL,
+    used_at         TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS device_challenges_expires_idx ON device_challenges (expires_at);
@@ -0,0 +1,10 @@
+-- Phase 4: Columns written by /v1/devices/register
+
+ALTER TABLE devices
+    ADD COLUMN IF NOT EXISTS platform TEXT,
+    ADD COLUMN IF NOT EXISTS identity_key_b64 TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS push_tokens_device_key ON push_tokens (device_id);
+
+COMMENT ON COLUMN devices.platform IS 'Client platform as reported at registration ("ios" or "android")';
+COMMENT ON COLUMN devices.identity_key_b64 IS 'Base64 Ed25519 identity public key reported at registration';
@@ -0,0 +1,21 @@
+-- Phase 4: Linked-device provisioning (QR-code pairing)
+
+-- Ephemeral mailbox a new device opens; its address is shown in the QR code.
+CREATE TABLE IF NOT EXISTS provisioning_channels (
+    address         UUID PRIMARY KEY,
+    blob_b64        TEXT,
+    expires_at      TIMESTAMPTZ NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- One-time codes an existing device mints and seals inside the provisioning blob.
+CREATE TABLE IF NOT EXISTS provisioning_codes (
+    code_hash       TEXT PRIMARY KEY,
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    issued_by_device UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    expires_at      TIMESTAMPTZ NOT NULL,
+    used_at         TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS provisioning_channels_expires_idx ON provisioning_channels (expires_at);
+CREATE INDEX IF NOT EXISTS provisioning_codes_expires_idx ON provisioning_codes (expires_at);
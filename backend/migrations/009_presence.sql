@@ -0,0 +1,14 @@
+-- Phase 4: Opt-in coarse presence sharing
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS share_presence BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS devices_last_seen_idx ON devices (last_seen_at) WHERE revoked_at IS NULL;
+
+-- Who may see a user's presence: an explicit allow-list kept by the user, not inferred from traffic.
+CREATE TABLE IF NOT EXISTS presence_viewers (
+    owner_user_id   UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    viewer_user_id  UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (owner_user_id, viewer_user_id)
+);
@@ -0,0 +1,14 @@
+-- Phase 4: Account deletion receipts and deferred object-storage purge
+-- Deliberately no FK to users: the row outlives the account it records.
+
+CREATE TABLE IF NOT EXISTS account_deletions (
+    id                  UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    storage_prefix      TEXT,
+    requested_at        TIMESTAMPTZ NOT NULL DEFAULT now(),
+    storage_purged_at   TIMESTAMPTZ,
+    attempts            INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS account_deletions_pending_idx ON account_deletions (requested_at) WHERE storage_purged_at IS NULL;
+
+COMMENT ON COLUMN account_deletions.storage_prefix IS 'S3 prefix still to purge; cleared once the purge completes';
@@ -0,0 +1,7 @@
+-- Phase 4: Usernames (`name.1234`) as a discovery identifier
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS username TEXT;
+
+-- Case-insensitive uniqueness of the full `name.discriminator`
+CREATE UNIQUE INDEX IF NOT EXISTS users_username_key ON users (lower(username)) WHERE username IS NOT NULL;
@@ -0,0 +1,7 @@
+-- Phase 4: Token bucket limiting contact discovery per account
+
+CREATE TABLE IF NOT EXISTS discovery_buckets (
+    user_id         UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    tokens          DOUBLE PRECISION NOT NULL,
+    updated_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
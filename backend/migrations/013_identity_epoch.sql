@@ -0,0 +1,4 @@
+-- Phase 4: Account identity epoch, bumped on recovery so contacts re-verify safety numbers
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS identity_epoch INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- Phase 4: Opt-in registration lock (PIN-derived token required to take over the account)
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS reglock_hash TEXT,
+    ADD COLUMN IF NOT EXISTS reglock_enabled_at TIMESTAMPTZ;
+
+COMMENT ON COLUMN users.reglock_hash IS 'SHA256 of the client''s PIN-derived registration lock token';
@@ -0,0 +1,10 @@
+-- Phase 4: Single-use proof-of-work seeds for /v1/auth/register
+
+CREATE TABLE IF NOT EXISTS registration_challenges (
+    seed            TEXT PRIMARY KEY,
+    difficulty      INTEGER NOT NULL,
+    expires_at      TIMESTAMPTZ NOT NULL,
+    used_at         TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS registration_challenges_expires_idx ON registration_challenges (expires_at);
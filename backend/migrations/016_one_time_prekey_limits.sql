@@ -0,0 +1,15 @@
+-- Phase 4: One-time prekeys are unique per device, so re-uploads are detected and skipped
+
+-- Keep one row per (device, key). A consumed copy wins over an unconsumed one so a key
+-- that was already handed out can't be handed out again; otherwise the oldest row stays.
+DELETE FROM one_time_prekeys a
+USING one_time_prekeys b
+WHERE a.device_id = b.device_id
+  AND a.prekey_x25519_b64 = b.prekey_x25519_b64
+  AND a.id <> b.id
+  AND (
+        (a.consumed_at IS NULL AND b.consumed_at IS NOT NULL)
+     OR ((a.consumed_at IS NULL) = (b.consumed_at IS NULL) AND a.id > b.id)
+  );
+
+CREATE UNIQUE INDEX IF NOT EXISTS one_time_prekeys_device_key_idx ON one_time_prekeys (device_id, prekey_x25519_b64);
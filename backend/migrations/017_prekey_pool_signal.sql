@@ -0,0 +1,18 @@
+-- Phase 4: One-time prekey pool refill signal, delivered through a per-device server notice queue
+
+CREATE TABLE IF NOT EXISTS server_notices (
+    id              UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id       UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    kind            TEXT NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS server_notices_device_idx ON server_notices (device_id, created_at);
+
+ALTER TABLE devices
+    ADD COLUMN IF NOT EXISTS prekeys_low_signaled_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS one_time_prekeys_consumed_idx ON one_time_prekeys (consumed_at) WHERE consumed_at IS NOT NULL;
+
+COMMENT ON COLUMN devices.prekeys_low_signaled_at IS 'Set when a prekeys_low notice was queued; cleared once an upload refills the pool';
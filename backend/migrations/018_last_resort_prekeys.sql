@@ -0,0 +1,7 @@
+-- Phase 4: Per-device last-resort prekey, served (never consumed) when the one-time pool is empty
+
+ALTER TABLE prekey_bundles
+    ADD COLUMN IF NOT EXISTS last_resort_prekey_x25519_b64 TEXT,
+    ADD COLUMN IF NOT EXISTS last_resort_prekey_signature_b64 TEXT;
+
+COMMENT ON COLUMN prekey_bundles.last_resort_prekey_signature_b64 IS 'Ed25519 signature by the identity key over the raw last-resort prekey bytes';
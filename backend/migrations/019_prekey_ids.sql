@@ -0,0 +1,17 @@
+-- Phase 4: Client-assigned prekey IDs, echoed in bundles so recipients know which prekeys a session used
+
+ALTER TABLE one_time_prekeys ADD COLUMN IF NOT EXISTS key_id INTEGER;
+
+-- Keys uploaded before IDs existed get per-device sequential IDs.
+UPDATE one_time_prekeys k
+SET key_id = r.n
+FROM (SELECT id, row_number() OVER (PARTITION BY device_id ORDER BY id) AS n FROM one_time_prekeys) r
+WHERE k.id = r.id AND k.key_id IS NULL;
+
+ALTER TABLE one_time_prekeys ALTER COLUMN key_id SET NOT NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS one_time_prekeys_device_key_id_idx ON one_time_prekeys (device_id, key_id);
+
+ALTER TABLE prekey_bundles
+    ADD COLUMN IF NOT EXISTS signed_prekey_id INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS last_resort_prekey_id INTEGER;
@@ -0,0 +1,19 @@
+-- Phase 4: Signed prekey history; superseded keys are kept for a grace period so late messages still decrypt
+
+CREATE TABLE IF NOT EXISTS signed_prekeys (
+    device_id       UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    key_id          INTEGER NOT NULL,
+    public_key_b64  TEXT NOT NULL,
+    signature_b64   TEXT NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    superseded_at   TIMESTAMPTZ, -- NULL for the device's current signed prekey
+    PRIMARY KEY (device_id, key_id)
+);
+
+CREATE INDEX IF NOT EXISTS signed_prekeys_superseded_idx ON signed_prekeys (superseded_at) WHERE superseded_at IS NOT NULL;
+
+INSERT INTO signed_prekeys (device_id, user_id, key_id, public_key_b64, signature_b64, created_at)
+SELECT device_id, user_id, signed_prekey_id, signed_prekey_x25519_b64, signed_prekey_signature_b64, created_at
+FROM prekey_bundles
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,17 @@
+-- Phase 4: Fingerprints of garbage-collected one-time prekeys, so a consumed key can't be uploaded again
+
+CREATE TABLE IF NOT EXISTS one_time_prekey_tombstones (
+    device_id       UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    key_sha256      BYTEA NOT NULL, -- sha256 of prekey_x25519_b64
+    key_id          INTEGER NOT NULL,
+    retired_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (device_id, key_sha256)
+);
+
+CREATE INDEX IF NOT EXISTS one_time_prekey_tombstones_retired_idx ON one_time_prekey_tombstones (retired_at);
+
+-- Tombstones are pruned after a while; the pruned key_ids are folded into this per-device floor instead.
+ALTER TABLE devices
+    ADD COLUMN IF NOT EXISTS one_time_prekey_id_floor INTEGER;
+
+COMMENT ON COLUMN devices.one_time_prekey_id_floor IS 'Highest key_id of a pruned one-time prekey tombstone; uploads at or below it are rejected';
@@ -0,0 +1,6 @@
+-- Phase 4: Per-user cutoff for access tokens, so a takeover also ends sessions that were never device-bound
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS access_tokens_valid_after TIMESTAMPTZ;
+
+COMMENT ON COLUMN users.access_tokens_valid_after IS 'Access tokens issued before this second are rejected';
@@ -0,0 +1,10 @@
+-- Phase 4: Fixed-window rate-limit counters (rate_limit::PostgresStore) upsert one row per key and window
+
+-- Windows are short-lived, so merging duplicates by keeping the highest count is enough.
+DELETE FROM rate_limits a
+USING rate_limits b
+WHERE a.key = b.key
+  AND a.window_start = b.window_start
+  AND (a.count, a.id) < (b.count, b.id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS rate_limits_key_window_key ON rate_limits (key, window_start);
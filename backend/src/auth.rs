@@ -1,17 +1,19 @@
 use crate::errors::ApiError;
 use crate::state::AppState;
-use axum::{extract::State, http::HeaderMap};
-use jsonwebtoken::{DecodingKey, Validation, Algorithm};
+use axum::{async_trait, extract::FromRequestParts, http::{request::Parts, HeaderMap}};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Uuid,          // user_id
     pub device: Option<Uuid>,
+    pub jti: Uuid,
     pub exp: usize,
 }
 
+/// Validates signature and `exp` only. Handlers should take `Claims` as an extractor instead,
+/// which also checks bans, device revocation and the token denylist.
 pub fn require_auth(headers: &HeaderMap, state: &AppState) -> Result<Claims, ApiError> {
     let auth = headers.get(axum::http::header::AUTHORIZATION)
         .and_then(|v| v.to_str().ok())
@@ -19,11 +21,23 @@ pub fn require_auth(headers: &HeaderMap, state: &AppState) -> Result<Claims, Api
 
     let token = auth.strip_prefix("Bearer ").ok_or(ApiError::Unauthorized)?;
 
-    let claims = jsonwebtoken::decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(state.jwt_secret.as_bytes()),
-        &Validation::new(Algorithm::HS256),
-    ).map_err(|_| ApiError::Unauthorized)?.claims;
+    state.keyring.verify::<Claims>(token)
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Claims {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let claims = require_auth(&parts.headers, state)?;
+
+        if state.revocation.is_revoked(&state.db, &claims).await? {
+            return Err(ApiError::Unauthorized);
+        }
+        if let Some(device_id) = claims.device {
+            state.last_seen.touch(&state.db, device_id);
+        }
 
-    Ok(claims)
+        Ok(claims)
+    }
 }
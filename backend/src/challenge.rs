@@ -0,0 +1,149 @@
+use std::{env, sync::Arc};
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use rand::Rng;
+use serde_json::{json, Value};
+use sha2::{Sha256, Digest};
+use sqlx::PgPool;
+use crate::errors::ApiError;
+
+/// Gate in front of `/auth/register` that makes mass account creation expensive.
+/// `issue` returns provider-specific parameters for the client; `verify` checks the
+/// client's `challenge` object from the register request. A captcha provider would
+/// implement the same trait and verify its token with the captcha service.
+#[async_trait]
+pub trait RegistrationChallenge: Send + Sync {
+    fn provider(&self) -> &'static str;
+    async fn issue(&self, db: &PgPool) -> Result<Value, ApiError>;
+    async fn verify(&self, db: &PgPool, response: Option<&Value>) -> Result<(), ApiError>;
+}
+
+/// DEV ONLY: accepts every registration.
+pub struct NoopChallenge;
+
+#[async_trait]
+impl RegistrationChallenge for NoopChallenge {
+    fn provider(&self) -> &'static str { "none" }
+
+    async fn issue(&self, _db: &PgPool) -> Result<Value, ApiError> {
+        Ok(json!({}))
+    }
+
+    async fn verify(&self, _db: &PgPool, _response: Option<&Value>) -> Result<(), ApiError> {
+        Ok(())
+    }
+}
+
+pub const POW_SEED_TTL_SECS: i64 = 300;
+
+/// Hashcash-style: find `nonce` so that SHA-256(`seed` ":" `nonce`) starts with
+/// `difficulty` zero bits. Each doubling of work is one more bit.
+pub struct ProofOfWork {
+    pub difficulty: u32,
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            bits += byte.leading_zeros();
+            break;
+        }
+    }
+    bits
+}
+
+/// Whether `nonce` solves `seed` at `difficulty` leading zero bits.
+fn solves(seed: &str, nonce: &str, difficulty: u32) -> bool {
+    let hash = Sha256::digest(format!("{}:{}", seed, nonce).as_bytes());
+    leading_zero_bits(&hash) >= difficulty
+}
+
+#[async_trait]
+impl RegistrationChallenge for ProofOfWork {
+    fn provider(&self) -> &'static str { "pow" }
+
+    async fn issue(&self, db: &PgPool) -> Result<Value, ApiError> {
+        let seed = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+        let expires_at = Utc::now() + Duration::seconds(POW_SEED_TTL_SECS);
+
+        sqlx::query!(
+            r#"INSERT INTO registration_challenges (seed, difficulty, expires_at) VALUES ($1, $2, $3)"#,
+            seed,
+            self.difficulty as i32,
+            expires_at
+        )
+        .execute(db)
+        .await
+        .map_err(|e| { tracing::error!("pow issue: {}", e); ApiError::Internal })?;
+
+        Ok(json!({ "seed": seed, "difficulty": self.difficulty, "expires_in": POW_SEED_TTL_SECS }))
+    }
+
+    async fn verify(&self, db: &PgPool, response: Option<&Value>) -> Result<(), ApiError> {
+        let invalid = || ApiError::BadRequest("invalid registration challenge".into());
+        let response = response.ok_or_else(invalid)?;
+        let seed = response.get("seed").and_then(Value::as_str).ok_or_else(invalid)?;
+        let nonce = response.get("nonce").and_then(Value::as_str).ok_or_else(invalid)?;
+        if nonce.len() > 64 {
+            return Err(invalid());
+        }
+
+        // Burn the seed first so a valid solution can't be replayed concurrently.
+        let issued = sqlx::query!(
+            r#"
+            UPDATE registration_challenges SET used_at = now()
+            WHERE seed = $1 AND used_at IS NULL AND expires_at > now()
+            RETURNING difficulty
+            "#,
+            seed
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(|e| { tracing::error!("pow verify: {}", e); ApiError::Internal })?
+        .ok_or_else(invalid)?;
+
+        if !solves(seed, nonce, issued.difficulty as u32) {
+            return Err(invalid());
+        }
+        Ok(())
+    }
+}
+
+/// `REGISTRATION_CHALLENGE` = "pow" (default) or "none"; `POW_DIFFICULTY` in bits (default 20).
+pub fn from_env() -> anyhow::Result<Arc<dyn RegistrationChallenge>> {
+    match env::var("REGISTRATION_CHALLENGE").unwrap_or("pow".to_string()).as_str() {
+        "pow" => {
+            let difficulty = env::var("POW_DIFFICULTY").ok().and_then(|v| v.parse().ok()).unwrap_or(20);
+            anyhow::ensure!(difficulty <= 32, "POW_DIFFICULTY must be at most 32");
+            Ok(Arc::new(ProofOfWork { difficulty }))
+        }
+        "none" => Ok(Arc::new(NoopChallenge)),
+        other => anyhow::bail!("unknown REGISTRATION_CHALLENGE: {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leading_zero_bits_counts_across_bytes() {
+        assert_eq!(leading_zero_bits(&[0x80, 0x00]), 0);
+        assert_eq!(leading_zero_bits(&[0x01, 0xff]), 7);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00, 0x0f, 0xff]), 20);
+        assert_eq!(leading_zero_bits(&[0u8; 32]), 256);
+    }
+
+    #[test]
+    fn solution_must_meet_difficulty_for_its_seed() {
+        // sha256("test-seed:531") = 000f8c92..., i.e. exactly 12 leading zero bits.
+        assert!(solves("test-seed", "531", 12));
+        assert!(!solves("test-seed", "531", 13));
+        assert!(!solves("other-seed", "531", 12));
+        assert!(!solves("test-seed", "0", 1));
+        assert!(solves("test-seed", "0", 0));
+    }
+}
@@ -0,0 +1,96 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{Duration, Utc};
+use ed25519_dalek::Verifier;
+use rand::Rng;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::{errors::ApiError, key_format};
+
+pub const CHALLENGE_TTL_SECS: i64 = 120;
+
+/// Domain-separates the signed bytes so a challenge signature can't be replayed as anything else.
+pub const CHALLENGE_CONTEXT: &[u8] = b"speakeasy-device-auth-v1:";
+
+pub struct Challenge {
+    pub id: Uuid,
+    pub nonce_b64: String,
+}
+
+/// Issues a challenge for one of `user_id`'s active devices; 404 for anything else.
+pub async fn create_challenge(db: &PgPool, user_id: Uuid, device_id: Uuid) -> Result<Challenge, ApiError> {
+    let id = Uuid::new_v4();
+    let nonce_b64 = STANDARD.encode(rand::thread_rng().gen::<[u8; 32]>());
+    let expires_at = Utc::now() + Duration::seconds(CHALLENGE_TTL_SECS);
+
+    let created = sqlx::query!(
+        r#"
+        INSERT INTO device_challenges (id, user_id, device_id, nonce_b64, expires_at)
+        SELECT $1, $2, d.id, $4, $5
+        FROM devices d
+        WHERE d.id = $3 AND d.user_id = $2 AND d.revoked_at IS NULL
+        "#,
+        id,
+        user_id,
+        device_id,
+        nonce_b64,
+        expires_at
+    )
+    .execute(db)
+    .await
+    .map_err(|e| { tracing::error!("challenge insert: {}", e); ApiError::Internal })?;
+
+    if created.rows_affected() == 0 {
+        return Err(ApiError::NotFound("Device not found".into()));
+    }
+    Ok(Challenge { id, nonce_b64 })
+}
+
+/// Burns the challenge and checks `signature_b64` over `CHALLENGE_CONTEXT || nonce` against the
+/// device's published identity key. Returns the device the challenge was issued for.
+pub async fn verify_challenge(
+    db: &PgPool,
+    user_id: Uuid,
+    challenge_id: Uuid,
+    signature_b64: &str,
+) -> Result<Uuid, ApiError> {
+    let challenge = sqlx::query!(
+        r#"
+        UPDATE device_challenges
+        SET used_at = now()
+        WHERE id = $1 AND user_id = $2 AND used_at IS NULL AND expires_at > now()
+        RETURNING device_id, nonce_b64
+        "#,
+        challenge_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| { tracing::error!("challenge lookup: {}", e); ApiError::Internal })?
+    .ok_or(ApiError::Unauthorized)?;
+
+    let bundle = sqlx::query!(
+        r#"
+        SELECT b.identity_key_ed25519_b64
+        FROM prekey_bundles b
+        JOIN devices d ON d.id = b.device_id
+        WHERE b.user_id = $1 AND b.device_id = $2 AND d.revoked_at IS NULL
+        "#,
+        user_id,
+        challenge.device_id
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| { tracing::error!("challenge identity key: {}", e); ApiError::Internal })?
+    .ok_or(ApiError::Unauthorized)?;
+
+    let key = key_format::identity_key(&bundle.identity_key_ed25519_b64).map_err(|_| ApiError::Unauthorized)?;
+    let signature = key_format::signature(signature_b64).map_err(|_| ApiError::Unauthorized)?;
+
+    let nonce = STANDARD.decode(&challenge.nonce_b64).map_err(|_| ApiError::Internal)?;
+    let mut message = CHALLENGE_CONTEXT.to_vec();
+    message.extend_from_slice(&nonce);
+
+    key.verify(&message, &signature).map_err(|_| ApiError::Unauthorized)?;
+
+    Ok(challenge.device_id)
+}
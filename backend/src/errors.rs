@@ -1,4 +1,4 @@
-use axum::{http::StatusCode, response::{IntoResponse, Response}};
+use axum::{http::{header, StatusCode}, response::{IntoResponse, Response}};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -11,6 +11,26 @@ pub enum ApiError {
     Internal,
     #[error("not found: {0}")]
     NotFound(String),
+    /// 429 with `Retry-After` (seconds).
+    #[error("rate limited")]
+    RateLimited(u64),
+    /// 409 with a stable machine-readable `code` (e.g. "identifier_taken").
+    #[error("conflict: {0}")]
+    Conflict(&'static str),
+    /// 423: the action needs an extra secret (e.g. "registration_lock").
+    #[error("locked: {0}")]
+    Locked(&'static str),
+    /// 400 for a rejected key or signature; `code` comes from `key_format::KeyError`.
+    #[error("invalid key: {0}")]
+    InvalidKey(&'static str),
+}
+
+/// Name of the violated unique constraint, if `e` is a unique violation.
+pub fn unique_violation(e: &sqlx::Error) -> Option<String> {
+    match e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => db.constraint().map(str::to_string),
+        _ => None,
+    }
 }
 
 impl IntoResponse for ApiError {
@@ -20,6 +40,26 @@ impl IntoResponse for ApiError {
             ApiError::BadRequest(s) => (StatusCode::BAD_REQUEST, s),
             ApiError::Internal => (StatusCode::INTERNAL_SERVER_ERROR, "internal".to_string()),
             ApiError::NotFound(s) => (StatusCode::NOT_FOUND, s),
+            ApiError::RateLimited(retry_after) => {
+                let body = serde_json::json!({ "error": "too many requests" });
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    axum::Json(body),
+                ).into_response();
+            }
+            ApiError::Conflict(code) => {
+                let body = serde_json::json!({ "error": "conflict", "code": code });
+                return (StatusCode::CONFLICT, axum::Json(body)).into_response();
+            }
+            ApiError::Locked(code) => {
+                let body = serde_json::json!({ "error": "locked", "code": code });
+                return (StatusCode::LOCKED, axum::Json(body)).into_response();
+            }
+            ApiError::InvalidKey(code) => {
+                let body = serde_json::json!({ "error": "invalid_key", "code": code });
+                return (StatusCode::BAD_REQUEST, axum::Json(body)).into_response();
+            }
         };
         (status, axum::Json(serde_json::json!({ "error": msg }))).into_response()
     }
@@ -0,0 +1,229 @@
+use std::env;
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Digest};
+use crate::{errors::ApiError, otp::OtpChannel};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentifierKind {
+    Phone,
+    Email,
+}
+
+/// A phone number or email after normalization. `raw` is kept only to find
+/// legacy hashes, which were computed over whatever string the client sent.
+#[derive(Debug, Clone)]
+pub struct Identifier {
+    pub kind: IdentifierKind,
+    pub normalized: String,
+    raw: String,
+}
+
+impl Identifier {
+    /// Normalizes to E.164. Separators are dropped and `00` is read as `+`.
+    /// Numbers must carry the international prefix: without a default region there is no
+    /// way to tell a national number like `(555) 010-0123` from one with a country code.
+    pub fn phone(raw: &str) -> Result<Self, ApiError> {
+        let trimmed = raw.trim();
+        let without_prefix = trimmed.strip_prefix('+')
+            .or_else(|| trimmed.strip_prefix("00"))
+            .ok_or(ApiError::BadRequest("phone number must start with + or 00 and the country code".into()))?;
+
+        let mut digits = String::with_capacity(without_prefix.len());
+        for c in without_prefix.chars() {
+            match c {
+                '0'..='9' => digits.push(c),
+                ' ' | '-' | '.' | '(' | ')' => {}
+                _ => return Err(ApiError::BadRequest("invalid phone number".into())),
+            }
+        }
+        if !(8..=15).contains(&digits.len()) || digits.starts_with('0') {
+            return Err(ApiError::BadRequest("invalid phone number".into()));
+        }
+
+        Ok(Self { kind: IdentifierKind::Phone, normalized: format!("+{}", digits), raw: raw.to_string() })
+    }
+
+    /// Trims and case-folds the whole address.
+    pub fn email(raw: &str) -> Result<Self, ApiError> {
+        let normalized = raw.trim().to_lowercase();
+        match normalized.split_once('@') {
+            Some((local, domain)) if !local.is_empty() && domain.contains('.') && !domain.contains('@') => {
+                Ok(Self { kind: IdentifierKind::Email, normalized, raw: raw.to_string() })
+            }
+            _ => Err(ApiError::BadRequest("invalid email".into())),
+        }
+    }
+
+    /// `SHA-256("phone:" || e164)` or `SHA-256("email:" || address)`. Clients compute the
+    /// same value for contact discovery; the pepper is applied on top of it.
+    pub fn prehash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(match self.kind {
+            IdentifierKind::Phone => b"phone:",
+            IdentifierKind::Email => b"email:",
+        });
+        hasher.update(self.normalized.as_bytes());
+        hasher.finalize().into()
+    }
+
+    /// Strings pre-pepper rows may have been hashed from: the server stored SHA-256 of whatever
+    /// the client sent, so a phone may be there as `+15550100123`, `15550100123` or `0015550100123`.
+    fn legacy_spellings(&self) -> Vec<String> {
+        let mut spellings = vec![self.raw.clone(), self.normalized.clone()];
+        if let (IdentifierKind::Phone, Some(digits)) = (self.kind, self.normalized.strip_prefix('+')) {
+            spellings.push(digits.to_string());
+            spellings.push(format!("00{}", digits));
+        }
+        spellings
+    }
+
+    pub fn channel(&self) -> OtpChannel {
+        match self.kind {
+            IdentifierKind::Phone => OtpChannel::Sms,
+            IdentifierKind::Email => OtpChannel::Email,
+        }
+    }
+}
+
+/// HMAC-SHA256 (under a server-held pepper) of the identifier's `prehash`.
+/// Stored hashes look like `v2$<hex>`; the version selects the pepper, so peppers
+/// can be rotated and old hashes are upgraded lazily on login.
+pub struct IdentifierHasher {
+    current: (u32, Vec<u8>),
+    previous: Vec<(u32, Vec<u8>)>,
+}
+
+impl IdentifierHasher {
+    /// `IDENTIFIER_PEPPERS` is a comma-separated list of `version:secret`.
+    /// The highest version hashes new identifiers; the rest are accepted for lookup only.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let spec = env::var("IDENTIFIER_PEPPERS")?;
+
+        let mut peppers = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (version, secret) = entry.split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("bad IDENTIFIER_PEPPERS entry"))?;
+            anyhow::ensure!(secret.len() >= 32, "identifier pepper v{} must be at least 32 bytes", version);
+            peppers.push((version.parse::<u32>()?, secret.as_bytes().to_vec()));
+        }
+        peppers.sort_by(|a, b| b.0.cmp(&a.0));
+
+        let mut peppers = peppers.into_iter();
+        let current = peppers.next().ok_or_else(|| anyhow::anyhow!("IDENTIFIER_PEPPERS is empty"))?;
+        Ok(Self { current, previous: peppers.collect() })
+    }
+
+    fn hash_with(version: u32, pepper: &[u8], prehash: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(pepper).expect("hmac accepts any key length");
+        mac.update(prehash);
+        format!("v{}${}", version, hex::encode(mac.finalize().into_bytes()))
+    }
+
+    /// The hash new rows (and OTP codes) are keyed by.
+    pub fn hash(&self, id: &Identifier) -> String {
+        Self::hash_with(self.current.0, &self.current.1, &id.prehash())
+    }
+
+    /// Stored forms (every pepper version) of a client-computed `Identifier::prehash`.
+    /// Used by contact discovery, where the server never sees the plaintext.
+    pub fn hashes_for_prehash(&self, prehash: &[u8]) -> Vec<String> {
+        std::iter::once(&self.current)
+            .chain(&self.previous)
+            .map(|(version, pepper)| Self::hash_with(*version, pepper, prehash))
+            .collect()
+    }
+
+    /// Every form this identifier may be stored under, current hash first:
+    /// older pepper versions, then the pre-pepper plain SHA-256 of the spellings clients
+    /// used to send (see `legacy_spellings`).
+    pub fn lookup_hashes(&self, id: &Identifier) -> Vec<String> {
+        let mut hashes = self.hashes_for_prehash(&id.prehash());
+        for legacy in id.legacy_spellings() {
+            let legacy_hash = hex::encode(Sha256::digest(legacy.as_bytes()));
+            if !hashes.contains(&legacy_hash) {
+                hashes.push(legacy_hash);
+            }
+        }
+        hashes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phone(raw: &str) -> Option<String> {
+        Identifier::phone(raw).ok().map(|id| id.normalized)
+    }
+
+    #[test]
+    fn phone_accepts_plus_and_00_prefixes() {
+        assert_eq!(phone("+15550100123").as_deref(), Some("+15550100123"));
+        assert_eq!(phone("0015550100123").as_deref(), Some("+15550100123"));
+        assert_eq!(phone("  +44 20 7946 0958 ").as_deref(), Some("+442079460958"));
+    }
+
+    #[test]
+    fn phone_drops_separators() {
+        assert_eq!(phone("+1 (555) 010-0123").as_deref(), Some("+15550100123"));
+        assert_eq!(phone("+1.555.010.0123").as_deref(), Some("+15550100123"));
+    }
+
+    #[test]
+    fn phone_rejects_numbers_without_international_prefix() {
+        assert_eq!(phone("(555) 010-0123"), None);
+        assert_eq!(phone("15550100123"), None);
+        assert_eq!(phone("0205550100"), None);
+    }
+
+    #[test]
+    fn phone_rejects_bad_lengths_and_characters() {
+        assert_eq!(phone("+1234567"), None); // 7 digits
+        assert_eq!(phone("+1234567890123456"), None); // 16 digits
+        assert_eq!(phone("+0123456789"), None); // country codes don't start with 0
+        assert_eq!(phone("+1555010012a"), None);
+        assert_eq!(phone("+"), None);
+    }
+
+    #[test]
+    fn lookup_hashes_cover_every_pepper_version() {
+        let hasher = IdentifierHasher {
+            current: (2, b"current-pepper-current-pepper-current".to_vec()),
+            previous: vec![(1, b"previous-pepper-previous-pepper-prev".to_vec())],
+        };
+        let id = Identifier::phone("+15550100123").unwrap();
+        let hashes = hasher.lookup_hashes(&id);
+
+        assert_eq!(hashes[0], hasher.hash(&id));
+        assert!(hashes[0].starts_with("v2$"));
+        assert_eq!(hashes[1], IdentifierHasher::hash_with(1, &hasher.previous[0].1, &id.prehash()));
+        // Discovery, which only sees the client's prehash, lands on the same stored hash.
+        assert_eq!(hasher.hashes_for_prehash(&id.prehash())[0], hashes[0]);
+    }
+
+    #[test]
+    fn lookup_hashes_find_pre_pepper_phones_sent_without_prefix() {
+        let hasher = IdentifierHasher {
+            current: (1, b"current-pepper-current-pepper-current".to_vec()),
+            previous: Vec::new(),
+        };
+        let id = Identifier::phone("+1 555 0100 123").unwrap();
+        let hashes = hasher.lookup_hashes(&id);
+
+        for stored in ["15550100123", "+15550100123", "0015550100123", "+1 555 0100 123"] {
+            let legacy = hex::encode(Sha256::digest(stored.as_bytes()));
+            assert!(hashes.contains(&legacy), "missing candidate for {}", stored);
+        }
+    }
+
+    #[test]
+    fn email_is_trimmed_and_lowercased() {
+        let id = Identifier::email("  Alice@Example.COM ").unwrap();
+        assert_eq!(id.normalized, "alice@example.com");
+        assert!(Identifier::email("alice@localhost").is_err());
+        assert!(Identifier::email("@example.com").is_err());
+        assert!(Identifier::email("a@b@example.com").is_err());
+    }
+}
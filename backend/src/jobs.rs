@@ -0,0 +1,287 @@
+use std::time::Duration;
+use crate::{routes::devices, state::AppState};
+
+const STALE_DEVICE_SWEEP_INTERVAL: Duration = Duration::from_secs(3600);
+const STORAGE_PURGE_INTERVAL: Duration = Duration::from_secs(300);
+const RATE_LIMIT_CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+const CONSUMED_PREKEY_GC_INTERVAL: Duration = Duration::from_secs(3600);
+const PREKEY_TOMBSTONE_PRUNE_INTERVAL: Duration = Duration::from_secs(86_400);
+const PREKEY_TOMBSTONE_RETENTION_DAYS: i32 = 180;
+const SIGNED_PREKEY_GC_INTERVAL: Duration = Duration::from_secs(3600);
+const REGISTRATION_CHALLENGE_GC_INTERVAL: Duration = Duration::from_secs(600);
+const EXPIRED_AUTH_GC_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Starts the periodic maintenance tasks. Each runs on its own interval and only logs failures.
+pub fn spawn_all(state: AppState) {
+    tokio::spawn(storage_purge(state.clone()));
+    tokio::spawn(rate_limit_cleanup(state.clone()));
+    tokio::spawn(consumed_prekey_gc(state.clone()));
+    tokio::spawn(prekey_tombstone_prune(state.clone()));
+    tokio::spawn(signed_prekey_gc(state.clone()));
+    tokio::spawn(registration_challenge_gc(state.clone()));
+    tokio::spawn(expired_auth_gc(state.clone()));
+    if state.stale_device_days > 0 {
+        tokio::spawn(stale_device_sweep(state));
+    }
+}
+
+/// Revokes devices that haven't been seen for `STALE_DEVICE_DAYS`.
+async fn stale_device_sweep(state: AppState) {
+    let mut interval = tokio::time::interval(STALE_DEVICE_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let stale = sqlx::query!(
+            r#"
+            SELECT id, user_id FROM devices
+            WHERE revoked_at IS NULL
+              AND COALESCE(last_seen_at, created_at) < now() - make_interval(days => $1)
+            LIMIT 500
+            "#,
+            state.stale_device_days as i32
+        )
+        .fetch_all(&state.db)
+        .await;
+
+        let stale = match stale {
+            Ok(rows) => rows,
+            Err(e) => { tracing::error!("stale device sweep: {}", e); continue; }
+        };
+
+        let mut revoked = 0;
+        for d in stale {
+            match devices::revoke_device(&state, d.user_id, d.id).await {
+                Ok(true) => revoked += 1,
+                Ok(false) => {}
+                Err(_) => tracing::error!("stale device revoke failed"),
+            }
+        }
+        if revoked > 0 {
+            tracing::info!("stale device sweep revoked {} devices", revoked);
+        }
+    }
+}
+
+/// Drops `rate_limits` windows that have ended; the longest rule window is a day.
+async fn rate_limit_cleanup(state: AppState) {
+    let mut interval = tokio::time::interval(RATE_LIMIT_CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let res = sqlx::query!(
+            r#"DELETE FROM rate_limits WHERE window_start < now() - interval '2 days'"#
+        )
+        .execute(&state.db)
+        .await;
+
+        if let Err(e) = res {
+            tracing::error!("rate limit cleanup: {}", e);
+        }
+    }
+}
+
+/// Deletes one-time prekeys consumed more than `OTK_RETENTION_DAYS` ago, leaving a
+/// fingerprint in `one_time_prekey_tombstones` so uploads still reject them.
+async fn consumed_prekey_gc(state: AppState) {
+    let mut interval = tokio::time::interval(CONSUMED_PREKEY_GC_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let res = sqlx::query!(
+            r#"
+            WITH gone AS (
+                DELETE FROM one_time_prekeys
+                WHERE consumed_at < now() - make_interval(days => $1)
+                RETURNING device_id, key_id, prekey_x25519_b64
+            )
+            INSERT INTO one_time_prekey_tombstones (device_id, key_sha256, key_id)
+            SELECT device_id, sha256(convert_to(prekey_x25519_b64, 'UTF8')), key_id FROM gone
+            ON CONFLICT DO NOTHING
+            "#,
+            state.otk_retention_days as i32
+        )
+        .execute(&state.db)
+        .await;
+
+        match res {
+            Ok(r) if r.rows_affected() > 0 => tracing::info!("prekey gc removed {} consumed keys", r.rows_affected()),
+            Ok(_) => {}
+            Err(e) => tracing::error!("prekey gc: {}", e),
+        }
+    }
+}
+
+/// Deletes tombstones older than `PREKEY_TOMBSTONE_RETENTION_DAYS`, raising each device's
+/// `one_time_prekey_id_floor` past their key_ids so those IDs stay rejected.
+async fn prekey_tombstone_prune(state: AppState) {
+    let mut interval = tokio::time::interval(PREKEY_TOMBSTONE_PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let res = sqlx::query!(
+            r#"
+            WITH pruned AS (
+                DELETE FROM one_time_prekey_tombstones
+                WHERE retired_at < now() - make_interval(days => $1)
+                RETURNING device_id, key_id
+            )
+            UPDATE devices d
+            SET one_time_prekey_id_floor = GREATEST(d.one_time_prekey_id_floor, p.max_key_id)
+            FROM (SELECT device_id, max(key_id) AS max_key_id FROM pruned GROUP BY device_id) p
+            WHERE d.id = p.device_id
+            "#,
+            PREKEY_TOMBSTONE_RETENTION_DAYS
+        )
+        .execute(&state.db)
+        .await;
+
+        match res {
+            Ok(r) if r.rows_affected() > 0 => tracing::info!("prekey tombstone prune raised the floor for {} devices", r.rows_affected()),
+            Ok(_) => {}
+            Err(e) => tracing::error!("prekey tombstone prune: {}", e),
+        }
+    }
+}
+
+/// Deletes signed prekeys superseded more than `SIGNED_PREKEY_GRACE_DAYS` ago.
+async fn signed_prekey_gc(state: AppState) {
+    let mut interval = tokio::time::interval(SIGNED_PREKEY_GC_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let res = sqlx::query!(
+            r#"DELETE FROM signed_prekeys WHERE superseded_at < now() - make_interval(days => $1)"#,
+            state.signed_prekey_grace_days as i32
+        )
+        .execute(&state.db)
+        .await;
+
+        match res {
+            Ok(r) if r.rows_affected() > 0 => tracing::info!("signed prekey gc removed {} keys", r.rows_affected()),
+            Ok(_) => {}
+            Err(e) => tracing::error!("signed prekey gc: {}", e),
+        }
+    }
+}
+
+/// Deletes expired proof-of-work seeds. Used and unused alike: verification already
+/// refuses anything past `expires_at`, so the rows are only dead weight.
+async fn registration_challenge_gc(state: AppState) {
+    let mut interval = tokio::time::interval(REGISTRATION_CHALLENGE_GC_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let res = sqlx::query!(
+            r#"DELETE FROM registration_challenges WHERE expires_at < now()"#
+        )
+        .execute(&state.db)
+        .await;
+
+        if let Err(e) = res {
+            tracing::error!("registration challenge gc: {}", e);
+        }
+    }
+}
+
+/// Deletes expired provisioning channels and codes, device challenges and access-token
+/// denylist entries. Every reader already ignores rows past `expires_at`; denylist entries get
+/// an extra hour so clock skew and JWT leeway can't resurrect a token.
+async fn expired_auth_gc(state: AppState) {
+    let mut interval = tokio::time::interval(EXPIRED_AUTH_GC_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let res = sqlx::query!(r#"DELETE FROM provisioning_channels WHERE expires_at < now()"#)
+            .execute(&state.db)
+            .await;
+        if let Err(e) = res {
+            tracing::error!("provisioning channel gc: {}", e);
+        }
+
+        let res = sqlx::query!(r#"DELETE FROM provisioning_codes WHERE expires_at < now()"#)
+            .execute(&state.db)
+            .await;
+        if let Err(e) = res {
+            tracing::error!("provisioning code gc: {}", e);
+        }
+
+        let res = sqlx::query!(r#"DELETE FROM device_challenges WHERE expires_at < now()"#)
+            .execute(&state.db)
+            .await;
+        if let Err(e) = res {
+            tracing::error!("device challenge gc: {}", e);
+        }
+
+        let res = sqlx::query!(r#"DELETE FROM revoked_tokens WHERE expires_at < now() - interval '1 hour'"#)
+            .execute(&state.db)
+            .await;
+        if let Err(e) = res {
+            tracing::error!("revoked token gc: {}", e);
+        }
+    }
+}
+
+/// Deletes S3 objects of deleted accounts. Rows stay pending (and are retried) until a
+/// full pass over the prefix succeeds.
+async fn storage_purge(state: AppState) {
+    let mut interval = tokio::time::interval(STORAGE_PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let pending = sqlx::query!(
+            r#"
+            SELECT id, storage_prefix AS "storage_prefix!"
+            FROM account_deletions
+            WHERE storage_purged_at IS NULL AND storage_prefix IS NOT NULL
+            ORDER BY requested_at
+            LIMIT 20
+            "#
+        )
+        .fetch_all(&state.db)
+        .await;
+
+        let pending = match pending {
+            Ok(rows) => rows,
+            Err(e) => { tracing::error!("storage purge lookup: {}", e); continue; }
+        };
+
+        for p in pending {
+            let purged = purge_prefix(&state, &p.storage_prefix).await;
+
+            let res = if purged {
+                sqlx::query!(
+                    r#"UPDATE account_deletions SET storage_purged_at = now(), storage_prefix = NULL WHERE id = $1"#,
+                    p.id
+                )
+                .execute(&state.db)
+                .await
+            } else {
+                sqlx::query!(
+                    r#"UPDATE account_deletions SET attempts = attempts + 1 WHERE id = $1"#,
+                    p.id
+                )
+                .execute(&state.db)
+                .await
+            };
+            if let Err(e) = res {
+                tracing::error!("storage purge update: {}", e);
+            }
+        }
+    }
+}
+
+async fn purge_prefix(state: &AppState, prefix: &str) -> bool {
+    let pages = match state.bucket.list(prefix.to_string(), None).await {
+        Ok(pages) => pages,
+        Err(e) => { tracing::error!("s3 list: {}", e); return false; }
+    };
+
+    let mut ok = true;
+    for object in pages.into_iter().flat_map(|p| p.contents) {
+        if let Err(e) = state.bucket.delete_object(&object.key).await {
+            tracing::error!("s3 delete: {}", e);
+            ok = false;
+        }
+    }
+    ok
+}
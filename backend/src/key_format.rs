@@ -0,0 +1,123 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use thiserror::Error;
+use crate::errors::ApiError;
+
+/// Why a client-supplied key or signature was refused. Surfaced to clients as a 400
+/// `{"error":"invalid_key","code":...}` so they can tell a bad upload from a transient failure.
+#[derive(Debug, Clone, Copy, Error)]
+pub enum KeyError {
+    #[error("public key is not base64 of 32 bytes")]
+    MalformedKey,
+    #[error("signature is not base64 of 64 bytes")]
+    MalformedSignature,
+    #[error("identity key is not a valid Ed25519 point")]
+    InvalidIdentityKey,
+    #[error("signature does not verify under the identity key")]
+    BadSignature,
+    #[error("identity key differs from the one registered for the device")]
+    IdentityMismatch,
+}
+
+impl KeyError {
+    pub fn code(self) -> &'static str {
+        match self {
+            KeyError::MalformedKey => "malformed_key",
+            KeyError::MalformedSignature => "malformed_signature",
+            KeyError::InvalidIdentityKey => "invalid_identity_key",
+            KeyError::BadSignature => "bad_signature",
+            KeyError::IdentityMismatch => "identity_mismatch",
+        }
+    }
+}
+
+impl From<KeyError> for ApiError {
+    fn from(e: KeyError) -> Self {
+        ApiError::InvalidKey(e.code())
+    }
+}
+
+/// Decodes a base64 32-byte public key (X25519 or Ed25519).
+pub fn public_key(b64: &str) -> Result<[u8; 32], KeyError> {
+    STANDARD.decode(b64)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or(KeyError::MalformedKey)
+}
+
+pub fn signature(b64: &str) -> Result<Signature, KeyError> {
+    let bytes: [u8; 64] = STANDARD.decode(b64)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or(KeyError::MalformedSignature)?;
+    Ok(Signature::from_bytes(&bytes))
+}
+
+/// Decodes an Ed25519 identity key, rejecting encodings that aren't curve points.
+pub fn identity_key(b64: &str) -> Result<VerifyingKey, KeyError> {
+    VerifyingKey::from_bytes(&public_key(b64)?).map_err(|_| KeyError::InvalidIdentityKey)
+}
+
+/// Checks that `identity_b64` signed the raw 32 bytes of the prekey `prekey_b64` (CryptoSpec §4.2).
+pub fn verify_signed_prekey(identity_b64: &str, prekey_b64: &str, signature_b64: &str) -> Result<(), KeyError> {
+    let identity = identity_key(identity_b64)?;
+    let prekey = public_key(prekey_b64)?;
+    let signature = signature(signature_b64)?;
+
+    identity.verify(&prekey, &signature).map_err(|_| KeyError::BadSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn b64(bytes: &[u8]) -> String {
+        STANDARD.encode(bytes)
+    }
+
+    fn signed_prekey(identity: &SigningKey) -> (String, String) {
+        let prekey = [9u8; 32];
+        (b64(&prekey), b64(&identity.sign(&prekey).to_bytes()))
+    }
+
+    #[test]
+    fn accepts_a_prekey_signed_by_the_identity_key() {
+        let identity = SigningKey::from_bytes(&[7u8; 32]);
+        let (prekey, sig) = signed_prekey(&identity);
+        verify_signed_prekey(&b64(identity.verifying_key().as_bytes()), &prekey, &sig).unwrap();
+    }
+
+    #[test]
+    fn rejects_signatures_from_another_key_or_over_another_prekey() {
+        let identity = SigningKey::from_bytes(&[7u8; 32]);
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        let (prekey, sig) = signed_prekey(&identity);
+
+        let err = verify_signed_prekey(&b64(other.verifying_key().as_bytes()), &prekey, &sig).unwrap_err();
+        assert!(matches!(err, KeyError::BadSignature));
+
+        let err = verify_signed_prekey(&b64(identity.verifying_key().as_bytes()), &b64(&[10u8; 32]), &sig).unwrap_err();
+        assert!(matches!(err, KeyError::BadSignature));
+    }
+
+    #[test]
+    fn rejects_malformed_inputs() {
+        let identity = SigningKey::from_bytes(&[7u8; 32]);
+        let identity_b64 = b64(identity.verifying_key().as_bytes());
+        let (prekey, sig) = signed_prekey(&identity);
+
+        let err = verify_signed_prekey(&identity_b64, &b64(&[9u8; 31]), &sig).unwrap_err();
+        assert!(matches!(err, KeyError::MalformedKey));
+        let err = verify_signed_prekey(&identity_b64, &prekey, &b64(&[0u8; 63])).unwrap_err();
+        assert!(matches!(err, KeyError::MalformedSignature));
+        let err = verify_signed_prekey("not base64!", &prekey, &sig).unwrap_err();
+        assert!(matches!(err, KeyError::MalformedKey));
+
+        // y = 2 has no x on the curve, so it doesn't decompress.
+        let mut off_curve = [0u8; 32];
+        off_curve[0] = 2;
+        let err = verify_signed_prekey(&b64(&off_curve), &prekey, &sig).unwrap_err();
+        assert!(matches!(err, KeyError::InvalidIdentityKey));
+    }
+}
@@ -0,0 +1,218 @@
+use std::{collections::HashMap, env, fs};
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use crate::errors::ApiError;
+
+/// kid used for `JWT_SECRET` and for tokens minted before kids existed.
+pub const LEGACY_KID: &str = "default";
+
+pub struct JwtKey {
+    pub kid: String,
+    pub alg: Algorithm,
+    encoding: Option<EncodingKey>, // None = verify-only (retired key)
+    decoding: DecodingKey,
+    jwk: Option<Value>,            // None for symmetric keys, which are never published
+}
+
+/// All keys we accept for verification, plus the one we sign new tokens with.
+pub struct Keyring {
+    active_kid: String,
+    keys: HashMap<String, JwtKey>,
+}
+
+impl Keyring {
+    /// `JWT_KEYS` is a comma-separated list of `kid:alg:private_path[:public_path]`:
+    ///   - `HS256`: `private_path` is a file holding the shared secret
+    ///   - `EdDSA` / `ES256`: PKCS#8 private PEM and SPKI public PEM; leave the
+    ///     private path empty (`kid:EdDSA::pub.pem`) for retired, verify-only keys
+    ///
+    /// `JWT_ACTIVE_KID` picks the signing key (defaults to the first entry).
+    /// `JWT_SECRET`, if set, stays accepted as HS256 under kid `default`.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut keys = HashMap::new();
+        let mut first_kid = None;
+
+        if let Ok(spec) = env::var("JWT_KEYS") {
+            for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let key = parse_key_spec(entry)?;
+                first_kid.get_or_insert_with(|| key.kid.clone());
+                keys.insert(key.kid.clone(), key);
+            }
+        }
+
+        if let Ok(secret) = env::var("JWT_SECRET") {
+            keys.entry(LEGACY_KID.to_string()).or_insert_with(|| hs256_key(LEGACY_KID, secret.as_bytes()));
+            first_kid.get_or_insert_with(|| LEGACY_KID.to_string());
+        }
+
+        let active_kid = env::var("JWT_ACTIVE_KID").ok()
+            .or(first_kid)
+            .ok_or_else(|| anyhow::anyhow!("no JWT keys configured (set JWT_KEYS or JWT_SECRET)"))?;
+
+        match keys.get(&active_kid) {
+            Some(k) if k.encoding.is_some() => {}
+            Some(_) => anyhow::bail!("active JWT key {} has no private key", active_kid),
+            None => anyhow::bail!("active JWT key {} not found", active_kid),
+        }
+
+        Ok(Self { active_kid, keys })
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, ApiError> {
+        let key = &self.keys[&self.active_kid];
+        let encoding = key.encoding.as_ref().ok_or(ApiError::Internal)?;
+
+        let mut header = Header::new(key.alg);
+        header.kid = Some(key.kid.clone());
+
+        jsonwebtoken::encode(&header, claims, encoding).map_err(|_| ApiError::Internal)
+    }
+
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<T, ApiError> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| ApiError::Unauthorized)?;
+        let kid = header.kid.as_deref().unwrap_or(LEGACY_KID);
+        let key = self.keys.get(kid).ok_or(ApiError::Unauthorized)?;
+
+        // Pin the algorithm to the key, never trust the header's `alg`.
+        let data = jsonwebtoken::decode::<T>(token, &key.decoding, &Validation::new(key.alg))
+            .map_err(|_| ApiError::Unauthorized)?;
+        Ok(data.claims)
+    }
+
+    /// Public keys in RFC 7517 JWK Set form. Symmetric keys are omitted.
+    pub fn jwks(&self) -> Value {
+        let keys: Vec<&Value> = self.keys.values().filter_map(|k| k.jwk.as_ref()).collect();
+        json!({ "keys": keys })
+    }
+}
+
+fn hs256_key(kid: &str, secret: &[u8]) -> JwtKey {
+    JwtKey {
+        kid: kid.to_string(),
+        alg: Algorithm::HS256,
+        encoding: Some(EncodingKey::from_secret(secret)),
+        decoding: DecodingKey::from_secret(secret),
+        jwk: None,
+    }
+}
+
+fn parse_key_spec(entry: &str) -> anyhow::Result<JwtKey> {
+    let parts: Vec<&str> = entry.split(':').collect();
+    let (kid, alg, private_path, public_path) = match parts.as_slice() {
+        [kid, alg, private_path] => (*kid, *alg, *private_path, ""),
+        [kid, alg, private_path, public_path] => (*kid, *alg, *private_path, *public_path),
+        _ => anyhow::bail!("bad JWT_KEYS entry: {}", entry),
+    };
+
+    match alg {
+        "HS256" => {
+            let secret = fs::read(private_path)?;
+            Ok(hs256_key(kid, secret.trim_ascii()))
+        }
+        "EdDSA" | "ES256" => {
+            let alg = if alg == "EdDSA" { Algorithm::EdDSA } else { Algorithm::ES256 };
+            let public_pem = fs::read(public_path)?;
+            let encoding = if private_path.is_empty() {
+                None
+            } else {
+                let private_pem = fs::read(private_path)?;
+                Some(match alg {
+                    Algorithm::EdDSA => EncodingKey::from_ed_pem(&private_pem)?,
+                    _ => EncodingKey::from_ec_pem(&private_pem)?,
+                })
+            };
+            let decoding = match alg {
+                Algorithm::EdDSA => DecodingKey::from_ed_pem(&public_pem)?,
+                _ => DecodingKey::from_ec_pem(&public_pem)?,
+            };
+            let jwk = public_jwk(kid, alg, &public_pem)?;
+            Ok(JwtKey { kid: kid.to_string(), alg, encoding, decoding, jwk: Some(jwk) })
+        }
+        other => anyhow::bail!("unsupported JWT algorithm {} for kid {}", other, kid),
+    }
+}
+
+// Fixed SPKI DER headers (algorithm identifier + BIT STRING tag) for the two key types.
+const ED25519_SPKI_PREFIX: &[u8] = &[0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+const P256_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01,
+    0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+/// Builds the JWK from an SPKI public key PEM. The raw key is the tail of the DER:
+/// 32 bytes for Ed25519, and an uncompressed `04 || x || y` point for P-256.
+fn public_jwk(kid: &str, alg: Algorithm, pem: &[u8]) -> anyhow::Result<Value> {
+    let body: String = std::str::from_utf8(pem)?
+        .lines()
+        .filter(|l| !l.starts_with("-----"))
+        .collect();
+    let der = STANDARD.decode(body.trim())?;
+
+    match alg {
+        Algorithm::EdDSA => {
+            anyhow::ensure!(
+                der.len() == 44 && der.starts_with(ED25519_SPKI_PREFIX),
+                "kid {}: not an Ed25519 SPKI public key", kid
+            );
+            Ok(json!({
+                "kty": "OKP", "crv": "Ed25519", "use": "sig", "alg": "EdDSA", "kid": kid,
+                "x": URL_SAFE_NO_PAD.encode(&der[12..]),
+            }))
+        }
+        _ => {
+            anyhow::ensure!(
+                der.len() == 91 && der.starts_with(P256_SPKI_PREFIX) && der[26] == 0x04,
+                "kid {}: not a P-256 SPKI public key", kid
+            );
+            Ok(json!({
+                "kty": "EC", "crv": "P-256", "use": "sig", "alg": "ES256", "kid": kid,
+                "x": URL_SAFE_NO_PAD.encode(&der[27..59]),
+                "y": URL_SAFE_NO_PAD.encode(&der[59..91]),
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519_PUB: &str = "-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEAoOsMTWv0Q3o6xFq355qsbIPBYSsTn6F+X6ABXEc+J8Y=
+-----END PUBLIC KEY-----
+";
+
+    const P256_PUB: &str = "-----BEGIN PUBLIC KEY-----
+MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAE8m3qMKvBS6y1jvj7GUIXCSx2N7WH
++FQ3PhcA/dY6lHnN25HB+zwjZ2Ij7tER4CQgHu/aK6VvBeUfpByuq8MoWg==
+-----END PUBLIC KEY-----
+";
+
+    #[test]
+    fn ed25519_jwk() {
+        let jwk = public_jwk("ed1", Algorithm::EdDSA, ED25519_PUB.as_bytes()).unwrap();
+        assert_eq!(jwk, json!({
+            "kty": "OKP", "crv": "Ed25519", "use": "sig", "alg": "EdDSA", "kid": "ed1",
+            "x": "oOsMTWv0Q3o6xFq355qsbIPBYSsTn6F-X6ABXEc-J8Y",
+        }));
+    }
+
+    #[test]
+    fn p256_jwk() {
+        let jwk = public_jwk("ec1", Algorithm::ES256, P256_PUB.as_bytes()).unwrap();
+        assert_eq!(jwk, json!({
+            "kty": "EC", "crv": "P-256", "use": "sig", "alg": "ES256", "kid": "ec1",
+            "x": "8m3qMKvBS6y1jvj7GUIXCSx2N7WH-FQ3PhcA_dY6lHk",
+            "y": "zduRwfs8I2diI-7REeAkIB7v2iulbwXlH6QcrqvDKFo",
+        }));
+    }
+
+    #[test]
+    fn jwk_rejects_the_wrong_key_type() {
+        assert!(public_jwk("ed1", Algorithm::ES256, ED25519_PUB.as_bytes()).is_err());
+        assert!(public_jwk("ec1", Algorithm::EdDSA, P256_PUB.as_bytes()).is_err());
+        assert!(public_jwk("bad", Algorithm::EdDSA, b"not a pem").is_err());
+    }
+}
@@ -1,11 +1,24 @@
-use axum::{routing::{get, post}, Router};
+use std::net::SocketAddr;
+use axum::{middleware, routing::get, Router};
 use tower_http::{trace::TraceLayer, cors::{CorsLayer, Any}};
 use tracing_subscriber::EnvFilter;
 
 mod state;
 mod routes;
 mod auth;
+mod challenge;
 mod errors;
+mod identifiers;
+mod jobs;
+mod device_auth;
+mod key_format;
+mod keyring;
+mod otp;
+mod presence;
+mod rate_limit;
+mod reglock;
+mod revocation;
+mod tokens;
 
 use state::AppState;
 
@@ -17,16 +30,19 @@ async fn main() -> anyhow::Result<()> {
         .init();
 
     let state = AppState::new_from_env().await?;
+    jobs::spawn_all(state.clone());
 
     let app = Router::new()
         .route("/health", get(routes::health))
+        .route("/.well-known/jwks.json", get(routes::jwks))
         .nest("/v1", routes::router())
+        .layer(middleware::from_fn_with_state(state.clone(), rate_limit::middleware))
         .layer(CorsLayer::new().allow_origin(Any).allow_methods(Any).allow_headers(Any))
         .layer(TraceLayer::new_for_http())
         .with_state(state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
     tracing::info!("listening on {}", listener.local_addr()?);
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
     Ok(())
 }
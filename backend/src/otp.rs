@@ -0,0 +1,205 @@
+use std::{env, path::PathBuf, sync::Arc};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use sha2::{Sha256, Digest};
+use sqlx::PgExecutor;
+use tokio::io::AsyncWriteExt;
+use crate::{errors::ApiError, state::AppState};
+
+pub const OTP_TTL_SECS: i64 = 300; // 5 minutes
+pub const OTP_MAX_ATTEMPTS: i32 = 5;
+pub const OTP_RESEND_COOLDOWN_SECS: i64 = 30;
+// Per identifier and across codes: a fresh code doesn't reset the guessing budget, and
+// re-requesting can't be used to send unlimited SMS.
+const OTP_FAILURES_PER_DAY: i32 = 10;
+const OTP_ISSUES_PER_DAY: i32 = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub enum OtpChannel {
+    Sms,
+    Email,
+}
+
+/// Delivers a one-time code to the user's phone or email.
+/// Production deployments plug in an SMS / email gateway here.
+#[async_trait]
+pub trait OtpSender: Send + Sync {
+    async fn send(&self, channel: OtpChannel, destination: &str, code: &str) -> Result<(), ApiError>;
+}
+
+/// DEV ONLY: writes codes to the tracing log instead of delivering them.
+pub struct LogOtpSender;
+
+#[async_trait]
+impl OtpSender for LogOtpSender {
+    async fn send(&self, channel: OtpChannel, _destination: &str, code: &str) -> Result<(), ApiError> {
+        // Destination is deliberately not logged (LoggingPolicy §2).
+        tracing::warn!("DEV OTP via {:?}: {}", channel, code);
+        Ok(())
+    }
+}
+
+/// DEV ONLY: appends `destination code` lines to a file so smoke tests can read them back.
+pub struct FileOtpSender {
+    pub path: PathBuf,
+}
+
+#[async_trait]
+impl OtpSender for FileOtpSender {
+    async fn send(&self, _channel: OtpChannel, destination: &str, code: &str) -> Result<(), ApiError> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| { tracing::error!("otp file open: {}", e); ApiError::Internal })?;
+
+        file.write_all(format!("{} {}\n", destination, code).as_bytes())
+            .await
+            .map_err(|e| { tracing::error!("otp file write: {}", e); ApiError::Internal })?;
+        Ok(())
+    }
+}
+
+/// Picks the sender from `OTP_SENDER` ("log" or "file"). There is deliberately no default:
+/// both senders expose codes, so a deploy that forgets the variable must not start.
+pub fn sender_from_env() -> anyhow::Result<Arc<dyn OtpSender>> {
+    let sender = env::var("OTP_SENDER")
+        .map_err(|_| anyhow::anyhow!("OTP_SENDER must be set (log and file are dev only)"))?;
+    match sender.as_str() {
+        "log" => Ok(Arc::new(LogOtpSender)),
+        "file" => {
+            let path = env::var("OTP_FILE").unwrap_or("otp_codes.txt".to_string());
+            Ok(Arc::new(FileOtpSender { path: path.into() }))
+        }
+        other => anyhow::bail!("unknown OTP_SENDER: {}", other),
+    }
+}
+
+/// Whole seconds until `at` for a `Retry-After` header, never less than one.
+fn secs_until(at: DateTime<Utc>) -> u64 {
+    (at - Utc::now()).num_seconds().max(1) as u64
+}
+
+fn generate_code() -> String {
+    format!("{:06}", rand::thread_rng().gen_range(0..1_000_000))
+}
+
+fn hash_code(identifier_hash: &str, code: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(identifier_hash.as_bytes());
+    hasher.update(b":");
+    hasher.update(code.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Creates (or replaces) the pending code for an identifier and returns the plaintext code.
+/// Only the hash of the code is stored.
+pub async fn issue(state: &AppState, identifier_hash: &str) -> Result<String, ApiError> {
+    let db = &state.db;
+    // An identifier that has used up its guesses gets no new codes until the window ends.
+    state.rate_limiter.ensure_below("otp_fail", identifier_hash, OTP_FAILURES_PER_DAY, 86_400).await?;
+
+    let existing = sqlx::query!(
+        r#"SELECT attempts, expires_at, created_at FROM otp_codes WHERE identifier_hash = $1"#,
+        identifier_hash
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| { tracing::error!("otp lookup: {}", e); ApiError::Internal })?;
+
+    if let Some(e) = existing {
+        let now = Utc::now();
+        // Locked out identifiers wait for the current code to expire before a new one is issued.
+        if e.attempts >= OTP_MAX_ATTEMPTS && e.expires_at > now {
+            return Err(ApiError::RateLimited(secs_until(e.expires_at)));
+        }
+        let resend_at = e.created_at + Duration::seconds(OTP_RESEND_COOLDOWN_SECS);
+        if now < resend_at {
+            return Err(ApiError::RateLimited(secs_until(resend_at)));
+        }
+    }
+
+    state.rate_limiter.check("otp_issue", identifier_hash, OTP_ISSUES_PER_DAY, 86_400).await?;
+
+    let code = generate_code();
+    let expires_at = Utc::now() + Duration::seconds(OTP_TTL_SECS);
+
+    sqlx::query!(
+        r#"
+        INSERT INTO otp_codes (identifier_hash, code_hash, attempts, expires_at, created_at)
+        VALUES ($1, $2, 0, $3, now())
+        ON CONFLICT (identifier_hash)
+        DO UPDATE SET
+            code_hash = EXCLUDED.code_hash,
+            attempts = 0,
+            expires_at = EXCLUDED.expires_at,
+            created_at = now()
+        "#,
+        identifier_hash,
+        hash_code(identifier_hash, &code),
+        expires_at
+    )
+    .execute(db)
+    .await
+    .map_err(|e| { tracing::error!("otp insert: {}", e); ApiError::Internal })?;
+
+    Ok(code)
+}
+
+/// Checks a submitted code and burns it on success. Every call counts as an attempt.
+pub async fn verify(state: &AppState, identifier_hash: &str, code: &str) -> Result<(), ApiError> {
+    check(state, identifier_hash, code).await?;
+    burn(&state.db, identifier_hash, code).await
+}
+
+/// Checks a submitted code without burning it, for callers that may still reject the request
+/// afterwards (registration of a taken identifier). Every call counts as an attempt, and
+/// wrong codes also count against the identifier's daily failure budget.
+pub async fn check(state: &AppState, identifier_hash: &str, code: &str) -> Result<(), ApiError> {
+    state.rate_limiter.ensure_below("otp_fail", identifier_hash, OTP_FAILURES_PER_DAY, 86_400).await?;
+
+    let pending = sqlx::query!(
+        r#"
+        UPDATE otp_codes
+        SET attempts = attempts + 1
+        WHERE identifier_hash = $1 AND expires_at > now()
+        RETURNING code_hash, attempts
+        "#,
+        identifier_hash
+    )
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("otp verify: {}", e); ApiError::Internal })?;
+
+    let pending = pending.ok_or(ApiError::Unauthorized)?;
+
+    if pending.attempts > OTP_MAX_ATTEMPTS {
+        return Err(ApiError::RateLimited(secs_until(pending.expires_at)));
+    }
+    if pending.code_hash != hash_code(identifier_hash, code) {
+        state.rate_limiter.check("otp_fail", identifier_hash, OTP_FAILURES_PER_DAY, 86_400).await?;
+        return Err(ApiError::Unauthorized);
+    }
+    Ok(())
+}
+
+/// Spends the code that passed `check`. Fails if a concurrent request already spent it or it was
+/// replaced by a newer code in the meantime, so running this inside a transaction makes that one
+/// code single-use for the transaction's work.
+pub async fn burn<'e, E: PgExecutor<'e>>(executor: E, identifier_hash: &str, code: &str) -> Result<(), ApiError> {
+    let burned = sqlx::query!(
+        r#"DELETE FROM otp_codes WHERE identifier_hash = $1 AND code_hash = $2"#,
+        identifier_hash,
+        hash_code(identifier_hash, code)
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| { tracing::error!("otp burn: {}", e); ApiError::Internal })?;
+
+    if burned.rows_affected() == 0 {
+        return Err(ApiError::Unauthorized);
+    }
+    Ok(())
+}
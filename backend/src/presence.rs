@@ -0,0 +1,42 @@
+use std::{collections::HashMap, sync::Mutex, time::{Duration, Instant}};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// `devices.last_seen_at` is written at most once per interval per device.
+pub const LAST_SEEN_WRITE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Remembers when each device's `last_seen_at` was last written so that
+/// authenticated requests don't turn into one UPDATE each.
+#[derive(Default)]
+pub struct LastSeenThrottle {
+    written: Mutex<HashMap<Uuid, Instant>>,
+}
+
+impl LastSeenThrottle {
+    /// Records activity for `device_id`. The write happens in the background; failures are only logged.
+    pub fn touch(&self, db: &PgPool, device_id: Uuid) {
+        {
+            let mut written = self.written.lock().unwrap();
+            if written.get(&device_id).is_some_and(|t| t.elapsed() < LAST_SEEN_WRITE_INTERVAL) {
+                return;
+            }
+            if written.len() >= 100_000 {
+                written.retain(|_, t| t.elapsed() < LAST_SEEN_WRITE_INTERVAL);
+            }
+            written.insert(device_id, Instant::now());
+        }
+
+        let db = db.clone();
+        tokio::spawn(async move {
+            let res = sqlx::query!(
+                r#"UPDATE devices SET last_seen_at = now() WHERE id = $1 AND revoked_at IS NULL"#,
+                device_id
+            )
+            .execute(&db)
+            .await;
+            if let Err(e) = res {
+                tracing::error!("last_seen update: {}", e);
+            }
+        });
+    }
+}
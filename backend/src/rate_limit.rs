@@ -0,0 +1,292 @@
+use std::{collections::HashMap, env, net::SocketAddr, sync::{Arc, Mutex}};
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use sqlx::PgPool;
+use crate::{auth::require_auth, errors::ApiError, state::AppState};
+
+/// What a limit is counted against. Authenticated keys fall back to the client IP
+/// when the request carries no (valid) token.
+#[derive(Debug, Clone, Copy)]
+pub enum RateKey {
+    Ip,
+    User,
+    Device,
+}
+
+/// One declarative per-route limit: at most `limit` requests per fixed `window_secs`.
+pub struct Rule {
+    pub method: &'static str,
+    pub path: &'static str, // route pattern as registered, e.g. "/v1/keys/bundle/:user_id"
+    pub key: RateKey,
+    pub limit: i32,
+    pub window_secs: i64,
+}
+
+pub const RULES: &[Rule] = &[
+    Rule { method: "POST", path: "/v1/auth/otp/request", key: RateKey::Ip, limit: 10, window_secs: 3600 },
+    Rule { method: "GET", path: "/v1/auth/register/challenge", key: RateKey::Ip, limit: 20, window_secs: 3600 },
+    // AbusePolicy §4: many accounts from a single IP
+    Rule { method: "POST", path: "/v1/auth/register", key: RateKey::Ip, limit: 5, window_secs: 3600 },
+    Rule { method: "POST", path: "/v1/auth/login", key: RateKey::Ip, limit: 20, window_secs: 3600 },
+    Rule { method: "POST", path: "/v1/auth/recover", key: RateKey::Ip, limit: 5, window_secs: 3600 },
+    Rule { method: "POST", path: "/v1/auth/refresh", key: RateKey::Ip, limit: 60, window_secs: 3600 },
+    Rule { method: "POST", path: "/v1/provisioning/channel", key: RateKey::Ip, limit: 10, window_secs: 3600 },
+    Rule { method: "POST", path: "/v1/provisioning/redeem", key: RateKey::Ip, limit: 10, window_secs: 3600 },
+    Rule { method: "POST", path: "/v1/keys/upload", key: RateKey::Device, limit: 30, window_secs: 3600 },
+    Rule { method: "POST", path: "/v1/keys/signed-prekey", key: RateKey::Device, limit: 10, window_secs: 3600 },
+    Rule { method: "GET", path: "/v1/keys/bundle/:user_id", key: RateKey::User, limit: 300, window_secs: 3600 },
+    Rule { method: "GET", path: "/v1/keys/bundle/:user_id/:device_id", key: RateKey::User, limit: 300, window_secs: 3600 },
+    Rule { method: "POST", path: "/v1/messages/send", key: RateKey::Device, limit: 300, window_secs: 60 },
+    Rule { method: "POST", path: "/v1/attachments/presign", key: RateKey::User, limit: 100, window_secs: 3600 },
+    Rule { method: "POST", path: "/v1/discovery/contacts", key: RateKey::User, limit: 60, window_secs: 3600 },
+    Rule { method: "POST", path: "/v1/reports/submit", key: RateKey::User, limit: 20, window_secs: 86_400 },
+];
+
+/// Fixed-window counter storage.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Counts one hit for `key` in the current window. Returns the count so far
+    /// and the seconds until the window resets.
+    async fn incr(&self, key: &str, window_secs: i64) -> Result<(i64, u64), ApiError>;
+
+    /// Like `incr`, but only reads the current window's count.
+    async fn current(&self, key: &str, window_secs: i64) -> Result<(i64, u64), ApiError>;
+}
+
+/// Counters in the `rate_limits` table, shared by every instance.
+pub struct PostgresStore {
+    pub db: PgPool,
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresStore {
+    async fn incr(&self, key: &str, window_secs: i64) -> Result<(i64, u64), ApiError> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO rate_limits (key, window_start, count)
+            VALUES ($1, to_timestamp(floor(extract(epoch FROM now()) / $2) * $2), 1)
+            ON CONFLICT (key, window_start)
+            DO UPDATE SET count = rate_limits.count + 1
+            RETURNING count, window_start
+            "#,
+            key,
+            window_secs as f64
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| { tracing::error!("rate limit: {}", e); ApiError::Internal })?;
+
+        let reset_in = (row.window_start.timestamp() + window_secs - Utc::now().timestamp()).max(1);
+        Ok((row.count as i64, reset_in as u64))
+    }
+
+    async fn current(&self, key: &str, window_secs: i64) -> Result<(i64, u64), ApiError> {
+        let row = sqlx::query!(
+            r#"
+            WITH w AS (SELECT to_timestamp(floor(extract(epoch FROM now()) / $2) * $2) AS start)
+            SELECT w.start AS "window_start!",
+                   (SELECT count FROM rate_limits WHERE key = $1 AND window_start = w.start) AS count
+            FROM w
+            "#,
+            key,
+            window_secs as f64
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| { tracing::error!("rate limit peek: {}", e); ApiError::Internal })?;
+
+        let reset_in = (row.window_start.timestamp() + window_secs - Utc::now().timestamp()).max(1);
+        Ok((row.count.unwrap_or(0) as i64, reset_in as u64))
+    }
+}
+
+/// Per-process counters, for tests and single-instance dev setups.
+#[derive(Default)]
+pub struct MemoryStore {
+    windows: Mutex<HashMap<String, (i64, i64)>>, // key -> (window_start, count)
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn incr(&self, key: &str, window_secs: i64) -> Result<(i64, u64), ApiError> {
+        let now = Utc::now().timestamp();
+        let window_start = now - now % window_secs;
+
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() >= 100_000 {
+            windows.retain(|_, (start, _)| *start >= window_start);
+        }
+        let entry = windows.entry(key.to_string()).or_insert((window_start, 0));
+        if entry.0 != window_start {
+            *entry = (window_start, 0);
+        }
+        entry.1 += 1;
+
+        Ok((entry.1, (window_start + window_secs - now).max(1) as u64))
+    }
+
+    async fn current(&self, key: &str, window_secs: i64) -> Result<(i64, u64), ApiError> {
+        let now = Utc::now().timestamp();
+        let window_start = now - now % window_secs;
+
+        let count = match self.windows.lock().unwrap().get(key) {
+            Some((start, count)) if *start == window_start => *count,
+            _ => 0,
+        };
+        Ok((count, (window_start + window_secs - now).max(1) as u64))
+    }
+}
+
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    /// `RATE_LIMIT_STORE` = "postgres" (default) or "memory".
+    pub fn from_env(db: &PgPool) -> anyhow::Result<Self> {
+        let store: Arc<dyn RateLimitStore> = match env::var("RATE_LIMIT_STORE").unwrap_or("postgres".to_string()).as_str() {
+            "postgres" => Arc::new(PostgresStore { db: db.clone() }),
+            "memory" => Arc::new(MemoryStore::default()),
+            other => anyhow::bail!("unknown RATE_LIMIT_STORE: {}", other),
+        };
+        Ok(Self { store })
+    }
+
+    /// Counts one hit for `scope:subject` and fails with 429 + Retry-After once `limit` is exceeded.
+    pub async fn check(&self, scope: &str, subject: &str, limit: i32, window_secs: i64) -> Result<(), ApiError> {
+        let (count, reset_in) = self.store.incr(&format!("{}:{}", scope, subject), window_secs).await?;
+
+        if count > limit as i64 {
+            // Scope only: subjects are user ids / IPs (LoggingPolicy §3)
+            tracing::warn!("rate limit exceeded: {}", scope);
+            return Err(ApiError::RateLimited(reset_in));
+        }
+        Ok(())
+    }
+
+    /// Fails with 429 + Retry-After if `scope:subject` has already used up `limit`, without
+    /// counting a hit. For limits that only count failures (see `reglock::enforce`).
+    pub async fn ensure_below(&self, scope: &str, subject: &str, limit: i32, window_secs: i64) -> Result<(), ApiError> {
+        let (count, reset_in) = self.store.current(&format!("{}:{}", scope, subject), window_secs).await?;
+
+        if count >= limit as i64 {
+            tracing::warn!("rate limit exceeded: {}", scope);
+            return Err(ApiError::RateLimited(reset_in));
+        }
+        Ok(())
+    }
+}
+
+/// With `TRUST_PROXY`, the rightmost X-Forwarded-For entry: the one our proxy appended.
+/// Everything left of it is client-supplied and would let callers pick their own bucket.
+fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, trust_proxy: bool) -> String {
+    if trust_proxy {
+        let forwarded = headers.get_all("x-forwarded-for")
+            .iter()
+            .last()
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .map(str::trim)
+            .filter(|v| !v.is_empty());
+        if let Some(ip) = forwarded {
+            return ip.to_string();
+        }
+    }
+    peer.map(|p| p.ip().to_string()).unwrap_or("unknown".to_string())
+}
+
+/// Applies the matching entry of `RULES`, if any, before the handler runs.
+pub async fn middleware(
+    State(state): State<AppState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    matched: Option<MatchedPath>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let rule = matched.and_then(|m| {
+        RULES.iter().find(|r| r.path == m.as_str() && r.method == req.method().as_str())
+    });
+
+    if let Some(rule) = rule {
+        let headers = req.headers();
+        let ip = || client_ip(headers, peer.map(|ConnectInfo(p)| p), state.trust_proxy);
+
+        // Signature check only; the handler's own extractor does the full revocation check.
+        let claims = match rule.key {
+            RateKey::Ip => None,
+            RateKey::User | RateKey::Device => require_auth(headers, &state).ok(),
+        };
+        let subject = match (rule.key, claims) {
+            (RateKey::Device, Some(c)) => c.device.map(|d| format!("device:{}", d)).unwrap_or(format!("user:{}", c.sub)),
+            (RateKey::User, Some(c)) => format!("user:{}", c.sub),
+            _ => format!("ip:{}", ip()),
+        };
+
+        let scope = format!("{} {}", rule.method, rule.path);
+        state.rate_limiter.check(&scope, &subject, rule.limit, rule.window_secs).await?;
+    }
+
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Long enough that a test never straddles a window boundary.
+    const WINDOW: i64 = 1_000_000_000;
+
+    #[tokio::test]
+    async fn memory_store_counts_hits_and_peeks_without_counting() {
+        let store = MemoryStore::default();
+        assert_eq!(store.current("a", WINDOW).await.unwrap().0, 0);
+        assert_eq!(store.incr("a", WINDOW).await.unwrap().0, 1);
+        assert_eq!(store.incr("a", WINDOW).await.unwrap().0, 2);
+        assert_eq!(store.current("a", WINDOW).await.unwrap().0, 2);
+        assert_eq!(store.current("a", WINDOW).await.unwrap().0, 2);
+        assert_eq!(store.incr("b", WINDOW).await.unwrap().0, 1);
+
+        let (_, reset_in) = store.incr("a", WINDOW).await.unwrap();
+        assert!(reset_in >= 1 && reset_in <= WINDOW as u64);
+    }
+
+    #[tokio::test]
+    async fn limiter_rejects_past_the_limit_with_retry_after() {
+        let limiter = RateLimiter { store: Arc::new(MemoryStore::default()) };
+        for _ in 0..3 {
+            limiter.ensure_below("otp_fail", "x", 3, WINDOW).await.unwrap();
+            limiter.check("otp_fail", "x", 3, WINDOW).await.unwrap();
+        }
+        assert!(matches!(limiter.ensure_below("otp_fail", "x", 3, WINDOW).await, Err(ApiError::RateLimited(s)) if s >= 1));
+        assert!(matches!(limiter.check("otp_fail", "x", 3, WINDOW).await, Err(ApiError::RateLimited(_))));
+        limiter.check("otp_fail", "y", 3, WINDOW).await.unwrap();
+    }
+
+    #[test]
+    fn client_ip_takes_the_rightmost_forwarded_entry() {
+        let peer: SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.append("x-forwarded-for", "6.6.6.6, 7.7.7.7".parse().unwrap());
+        headers.append("x-forwarded-for", "1.1.1.1, 203.0.113.9 ".parse().unwrap());
+
+        assert_eq!(client_ip(&headers, Some(peer), true), "203.0.113.9");
+        assert_eq!(client_ip(&headers, Some(peer), false), "10.0.0.1");
+    }
+
+    #[test]
+    fn client_ip_falls_back_to_the_peer() {
+        let peer: SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_ip(&headers, Some(peer), true), "10.0.0.1");
+
+        headers.insert("x-forwarded-for", "1.1.1.1, ".parse().unwrap());
+        assert_eq!(client_ip(&headers, Some(peer), true), "10.0.0.1");
+        assert_eq!(client_ip(&headers, None, true), "unknown");
+    }
+}
@@ -0,0 +1,52 @@
+use chrono::{Duration, Utc};
+use sha2::{Sha256, Digest};
+use uuid::Uuid;
+use crate::{errors::ApiError, state::AppState};
+
+/// The lock lapses once none of the account's devices has been seen for this long,
+/// so a user who lost every device and forgot the PIN isn't locked out forever.
+pub const REGLOCK_INACTIVITY_DAYS: i64 = 30;
+const REGLOCK_FAILURES_PER_DAY: i32 = 5;
+
+/// The client derives the token from the PIN (CryptoSpec §3.3); it is high-entropy,
+/// so a plain SHA-256 is enough to store it.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Fails with 423 if `user_id` has an active lock and `token` doesn't match it.
+/// Callers must have verified the OTP first, so the lock's existence only leaks to the identifier owner.
+pub async fn enforce(state: &AppState, user_id: Uuid, token: Option<&str>) -> Result<(), ApiError> {
+    let lock = sqlx::query!(
+        r#"
+        SELECT u.reglock_hash, u.reglock_enabled_at,
+               (SELECT MAX(d.last_seen_at) FROM devices d WHERE d.user_id = u.id) AS last_seen_at
+        FROM users u
+        WHERE u.id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("reglock lookup: {}", e); ApiError::Internal })?;
+
+    let Some(expected) = lock.reglock_hash else { return Ok(()) };
+
+    let last_activity = lock.last_seen_at.max(lock.reglock_enabled_at);
+    if last_activity.is_none_or(|t| Utc::now() - t > Duration::days(REGLOCK_INACTIVITY_DAYS)) {
+        return Ok(());
+    }
+
+    // Only wrong tokens count, so PIN guessing is capped even across fresh OTPs
+    // without the owner's successful attempts eating into the budget.
+    let subject = user_id.to_string();
+    state.rate_limiter.ensure_below("reglock", &subject, REGLOCK_FAILURES_PER_DAY, 86_400).await?;
+
+    match token {
+        Some(t) if hash_token(t) == expected => Ok(()),
+        _ => {
+            state.rate_limiter.check("reglock", &subject, REGLOCK_FAILURES_PER_DAY, 86_400).await?;
+            Err(ApiError::Locked("registration_lock"))
+        }
+    }
+}
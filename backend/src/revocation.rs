@@ -0,0 +1,109 @@
+use std::{collections::HashMap, sync::Mutex, time::{Duration, Instant}};
+use chrono::{TimeZone, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::{auth::Claims, errors::ApiError, tokens::ACCESS_TOKEN_TTL_SECS};
+
+/// How long a positive/negative revocation lookup is trusted before Postgres is asked again.
+/// Local invalidation is immediate; other instances converge within this window.
+pub const REVOCATION_CACHE_TTL: Duration = Duration::from_secs(30);
+const MAX_CACHE_ENTRIES: usize = 50_000;
+
+struct Entry {
+    user_id: Uuid,
+    device_id: Option<Uuid>,
+    revoked: bool,
+    checked_at: Instant,
+}
+
+/// In-process cache of "is this token still allowed" keyed by `jti`.
+#[derive(Default)]
+pub struct RevocationCache {
+    entries: Mutex<HashMap<Uuid, Entry>>,
+}
+
+impl RevocationCache {
+    pub async fn is_revoked(&self, db: &PgPool, claims: &Claims) -> Result<bool, ApiError> {
+        if let Some(e) = self.entries.lock().unwrap().get(&claims.jti) {
+            if e.checked_at.elapsed() < REVOCATION_CACHE_TTL {
+                return Ok(e.revoked);
+            }
+        }
+
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                EXISTS(
+                    SELECT 1 FROM users
+                    WHERE id = $1 AND status = 'active' AND banned_at IS NULL
+                      -- issued-at is exp minus the fixed TTL
+                      AND (access_tokens_valid_after IS NULL OR access_tokens_valid_after <= to_timestamp($4))
+                ) AS "user_active!",
+                EXISTS(SELECT 1 FROM devices WHERE id = $2 AND revoked_at IS NOT NULL) AS "device_revoked!",
+                EXISTS(SELECT 1 FROM revoked_tokens WHERE jti = $3) AS "token_revoked!"
+            "#,
+            claims.sub,
+            claims.device,
+            claims.jti,
+            (claims.exp as i64 - ACCESS_TOKEN_TTL_SECS) as f64
+        )
+        .fetch_one(db)
+        .await
+        .map_err(|e| { tracing::error!("revocation check: {}", e); ApiError::Internal })?;
+
+        let revoked = !row.user_active || row.device_revoked || row.token_revoked;
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHE_ENTRIES {
+            entries.retain(|_, e| e.checked_at.elapsed() < REVOCATION_CACHE_TTL);
+        }
+        if entries.len() >= MAX_CACHE_ENTRIES {
+            // Still full of live entries: drop the oldest tenth. Evicted tokens just go back to Postgres.
+            let mut by_age: Vec<(Instant, Uuid)> = entries.iter().map(|(jti, e)| (e.checked_at, *jti)).collect();
+            by_age.sort_unstable();
+            for (_, jti) in by_age.into_iter().take(MAX_CACHE_ENTRIES / 10) {
+                entries.remove(&jti);
+            }
+        }
+        entries.insert(claims.jti, Entry {
+            user_id: claims.sub,
+            device_id: claims.device,
+            revoked,
+            checked_at: Instant::now(),
+        });
+
+        Ok(revoked)
+    }
+
+    /// Drop cached decisions for a user (ban, status change, logout everywhere).
+    pub fn forget_user(&self, user_id: Uuid) {
+        self.entries.lock().unwrap().retain(|_, e| e.user_id != user_id);
+    }
+
+    /// Drop cached decisions for a device (device revoked).
+    pub fn forget_device(&self, device_id: Uuid) {
+        self.entries.lock().unwrap().retain(|_, e| e.device_id != Some(device_id));
+    }
+
+    /// Deny a single access token until it would have expired anyway.
+    pub async fn deny_token(&self, db: &PgPool, claims: &Claims) -> Result<(), ApiError> {
+        let expires_at = Utc.timestamp_opt(claims.exp as i64, 0).single().unwrap_or_else(Utc::now);
+
+        sqlx::query!(
+            r#"
+            INSERT INTO revoked_tokens (jti, user_id, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (jti) DO NOTHING
+            "#,
+            claims.jti,
+            claims.sub,
+            expires_at
+        )
+        .execute(db)
+        .await
+        .map_err(|e| { tracing::error!("deny token: {}", e); ApiError::Internal })?;
+
+        self.entries.lock().unwrap().remove(&claims.jti);
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use axum::{routing::delete, Router, extract::State, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::{state::AppState, errors::ApiError, auth::Claims, device_auth, otp};
+use super::auth_routes::otp_target;
+
+/// Deletion must be re-authorized: either a fresh OTP for one of the account's
+/// identifiers, or a signed device challenge from `/auth/device/challenge`.
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountReq {
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    pub code: Option<String>,
+    pub challenge_id: Option<Uuid>,
+    pub signature_b64: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeletionReceipt {
+    pub deletion_id: Uuid,
+    pub deleted_at: DateTime<Utc>,
+    pub attachments_purge: &'static str, // "queued"
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/account", delete(delete_account))
+}
+
+pub async fn delete_account(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(req): Json<DeleteAccountReq>,
+) -> Result<Json<DeletionReceipt>, ApiError> {
+    match (&req.code, &req.challenge_id, &req.signature_b64) {
+        (Some(code), _, _) => {
+            let identifier = otp_target(&req.phone, &req.email)?;
+            let identifier_hash = state.identifiers.hash(&identifier);
+            let candidates = state.identifiers.lookup_hashes(&identifier);
+
+            let owned = sqlx::query!(
+                r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM users WHERE id = $1 AND (phone_hash = ANY($2) OR email_hash = ANY($2))
+                ) AS "owned!"
+                "#,
+                claims.sub,
+                &candidates
+            )
+            .fetch_one(&state.db)
+            .await
+            .map_err(|e| { tracing::error!("delete identifier check: {}", e); ApiError::Internal })?;
+
+            if !owned.owned {
+                return Err(ApiError::Unauthorized);
+            }
+            otp::verify(&state, &identifier_hash, code).await?;
+        }
+        (None, Some(challenge_id), Some(signature_b64)) => {
+            device_auth::verify_challenge(&state.db, claims.sub, *challenge_id, signature_b64).await?;
+        }
+        _ => return Err(ApiError::BadRequest("code or signed challenge required".into())),
+    }
+
+    let mut tx = state.db.begin().await
+        .map_err(|e| { tracing::error!("db begin: {}", e); ApiError::Internal })?;
+
+    // Attachments live under `{user_id}/` (see attachments::presign); purged by jobs::storage_purge.
+    let receipt = sqlx::query!(
+        r#"
+        INSERT INTO account_deletions (storage_prefix)
+        VALUES ($1)
+        RETURNING id, requested_at
+        "#,
+        format!("{}/", claims.sub)
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| { tracing::error!("deletion receipt: {}", e); ApiError::Internal })?;
+
+    // devices, prekey_bundles, one_time_prekeys, messages, attachments, push_tokens, backups,
+    // blocked_users and token tables all cascade from users.
+    sqlx::query!(r#"DELETE FROM users WHERE id = $1"#, claims.sub)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| { tracing::error!("delete user: {}", e); ApiError::Internal })?;
+
+    tx.commit().await
+        .map_err(|e| { tracing::error!("db commit: {}", e); ApiError::Internal })?;
+
+    state.revocation.forget_user(claims.sub);
+
+    Ok(Json(DeletionReceipt {
+        deletion_id: receipt.id,
+        deleted_at: receipt.requested_at,
+        attachments_purge: "queued",
+    }))
+}
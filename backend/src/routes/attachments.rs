@@ -1,9 +1,9 @@
 use axum::{
-    routing::{get, post}, Router, extract::{State, Path}, Json, http::HeaderMap
+    routing::{get, post}, Router, extract::{State, Path}, Json
 };
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use crate::{state::AppState, errors::ApiError, auth::require_auth};
+use crate::{state::AppState, errors::ApiError, auth::Claims};
 
 #[derive(Debug, Deserialize)]
 pub struct PresignReq {
@@ -53,11 +53,9 @@ pub fn router() -> Router<AppState> {
 
 pub async fn presign(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    claims: Claims,
     Json(req): Json<PresignReq>,
 ) -> Result<Json<PresignResp>, ApiError> {
-    let claims = require_auth(&headers, &state)?;
-    
     let attachment_id = Uuid::new_v4();
     let storage_key = format!("{}/{}.bin", claims.sub, attachment_id);
     let expires_in = 600; // 10 minutes
@@ -89,11 +87,9 @@ pub async fn presign(
 
 pub async fn get_download_url(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    _claims: Claims,
     Path(attachment_id): Path<Uuid>,
 ) -> Result<Json<DownloadResp>, ApiError> {
-    let _claims = require_auth(&headers, &state)?;
-
     let meta = sqlx::query!(
         r#"SELECT storage_key FROM attachments WHERE id = $1 AND deleted = false"#,
         attachment_id
@@ -115,11 +111,9 @@ pub async fn get_download_url(
 
 pub async fn complete_upload(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    claims: Claims,
     Json(req): Json<CompleteReq>,
 ) -> Result<Json<OkResp>, ApiError> {
-    let claims = require_auth(&headers, &state)?;
-
     // Verify attachment exists and belongs to user
     let existing = sqlx::query!(
         r#"SELECT owner_user_id FROM attachments WHERE id = $1"#,
@@ -1,51 +1,206 @@
-use axum::{routing::post, Router, extract::State, Json};
+use axum::{routing::{get, post}, Router, extract::State, Json};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use chrono::{Utc, Duration};
-use jsonwebtoken::{EncodingKey, Header, Algorithm};
-use sha2::{Sha256, Digest};
-use crate::{state::AppState, errors::ApiError, auth::Claims};
+use super::devices::{self, ExistingDevice, RegisterDeviceReq};
+use crate::{state::AppState, errors::{ApiError, unique_violation}, auth::Claims, keyring::Keyring, otp, reglock, tokens, device_auth, identifiers::{Identifier, IdentifierKind}};
 
 #[derive(Debug, Deserialize)]
 pub struct RegisterReq {
     pub display_name: String,
     pub phone: Option<String>,
     pub email: Option<String>,
-    pub device_id: Option<Uuid>, 
+    pub code: String,          // OTP from /auth/otp/request for the phone (or email)
+    #[serde(default)]
+    pub reregister: bool,      // take the identifier over from the account currently holding it
+    pub registration_lock: Option<String>, // required with `reregister` if the holder has a lock
+    pub challenge: Option<serde_json::Value>, // answer to GET /auth/register/challenge
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegisterChallengeResp {
+    pub provider: &'static str,
+    #[serde(flatten)]
+    pub params: serde_json::Value,
 }
 
 #[derive(Debug, Serialize)]
 pub struct AuthResp {
     pub user_id: Uuid,
     pub access_token: String,
+    pub expires_in: i64,
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshReq {
+    pub refresh_token: String,
 }
 
+#[derive(Debug, Serialize)]
+pub struct LogoutResp { pub ok: bool }
+
 #[derive(Debug, Deserialize)]
 pub struct LoginReq {
     pub phone: Option<String>,
     pub email: Option<String>,
     pub code: String,
-    pub device_id: Option<Uuid>,
+    pub registration_lock: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceChallengeReq {
+    pub device_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceChallengeResp {
+    pub challenge_id: Uuid,
+    pub nonce_b64: String,
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceVerifyReq {
+    pub challenge_id: Uuid,
+    pub signature_b64: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecoverReq {
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    pub code: String,
+    pub device_id: Uuid,
+    pub platform: String,
+    pub identity_key: String,
+    pub push_token: Option<String>,
+    pub registration_lock: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoverResp {
+    #[serde(flatten)]
+    pub auth: AuthResp,
+    pub identity_epoch: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OtpReq {
+    pub phone: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OtpResp {
+    pub ok: bool,
+    pub expires_in: i64,
 }
 
 pub fn router() -> Router<AppState> {
     Router::new()
+        .route("/auth/otp/request", post(request_otp))
+        .route("/auth/register/challenge", get(register_challenge))
         .route("/auth/register", post(register))
         .route("/auth/login", post(login))
+        .route("/auth/recover", post(recover))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/logout", post(logout))
+        .route("/auth/device/challenge", post(device_challenge))
+        .route("/auth/device/verify", post(device_verify))
+}
+
+/// Exactly one identifier is verified per request; phone wins if both are sent.
+pub fn otp_target(phone: &Option<String>, email: &Option<String>) -> Result<Identifier, ApiError> {
+    match (phone, email) {
+        (Some(p), _) => Identifier::phone(p),
+        (None, Some(e)) => Identifier::email(e),
+        (None, None) => Err(ApiError::BadRequest("phone or email required".into())),
+    }
 }
 
-fn hash_identifier(input: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(input.as_bytes());
-    hex::encode(hasher.finalize())
+pub async fn register_challenge(
+    State(state): State<AppState>,
+) -> Result<Json<RegisterChallengeResp>, ApiError> {
+    let params = state.registration_challenge.issue(&state.db).await?;
+    Ok(Json(RegisterChallengeResp { provider: state.registration_challenge.provider(), params }))
 }
 
 pub async fn register(
     State(state): State<AppState>,
     Json(req): Json<RegisterReq>,
 ) -> Result<Json<AuthResp>, ApiError> {
-    let phone_hash = req.phone.as_ref().map(|s| hash_identifier(s));
-    let email_hash = req.email.as_ref().map(|s| hash_identifier(s));
+    if req.display_name.trim().is_empty() {
+        return Err(ApiError::BadRequest("display_name required".into()));
+    }
+
+    state.registration_challenge.verify(&state.db, req.challenge.as_ref()).await?;
+
+    // Prove ownership first so the conflict answer below can't be used to probe identifiers.
+    // The code is only spent together with the new account, so a client that gets
+    // `identifier_taken` can retry with `reregister` using the same code.
+    // Only the verified identifier is stored.
+    let identifier = otp_target(&req.phone, &req.email)?;
+    let identifier_hash = state.identifiers.hash(&identifier);
+    otp::check(&state, &identifier_hash, &req.code).await?;
+
+    let is_phone = identifier.kind == IdentifierKind::Phone;
+    let candidates = state.identifiers.lookup_hashes(&identifier);
+    let (phone_hash, email_hash) = if is_phone {
+        (Some(identifier_hash.clone()), None)
+    } else {
+        (None, Some(identifier_hash.clone()))
+    };
+
+    let mut tx = state.db.begin().await
+        .map_err(|e| { tracing::error!("db begin: {}", e); ApiError::Internal })?;
+
+    let holder = sqlx::query!(
+        r#"
+        SELECT id, status = 'active' AND banned_at IS NULL AS "in_good_standing!" FROM users
+        WHERE ($2 AND phone_hash = ANY($1))
+           OR (NOT $2 AND email_hash = ANY($1))
+        FOR UPDATE
+        "#,
+        &candidates,
+        is_phone
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| { tracing::error!("register holder lookup: {}", e); ApiError::Internal })?;
+
+    let previous_holder = holder.as_ref().map(|h| h.id);
+    if let Some(holder) = holder {
+        if !req.reregister {
+            return Err(ApiError::Conflict("identifier_taken"));
+        }
+        // Otherwise a banned user could move their number onto a fresh account.
+        if !holder.in_good_standing {
+            return Err(ApiError::Conflict("identifier_banned"));
+        }
+        reglock::enforce(&state, holder.id, req.registration_lock.as_deref()).await?;
+
+        // Re-registration: the verified identifier moves to the new account.
+        sqlx::query!(
+            r#"
+            UPDATE users SET
+                phone_hash = CASE WHEN $2 THEN NULL ELSE phone_hash END,
+                email_hash = CASE WHEN $2 THEN email_hash ELSE NULL END,
+                updated_at = now()
+            WHERE id = $1
+            "#,
+            holder.id,
+            is_phone
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| { tracing::error!("register detach identifier: {}", e); ApiError::Internal })?;
+
+        // Whoever held the number loses the account's devices and sessions with it.
+        devices::revoke_all_devices_in(&mut tx, holder.id).await?;
+
+        tracing::info!("identifier re-registered away from an existing account");
+    }
 
     let user_id = Uuid::new_v4();
 
@@ -60,67 +215,277 @@ pub async fn register(
         phone_hash,
         email_hash
     )
-    .execute(&state.db)
+    .execute(&mut *tx)
     .await
-    .map_err(|e| {
-        // Handle uniqueness constraint violation?
-        tracing::error!("register db: {}", e);
-        ApiError::Internal 
+    .map_err(|e| match unique_violation(&e).as_deref() {
+        // Lost a race with a concurrent registration of the same identifier
+        Some("users_phone_hash_key") | Some("users_email_hash_key") => ApiError::Conflict("identifier_taken"),
+        _ => { tracing::error!("register db: {}", e); ApiError::Internal }
     })?;
 
-    // Issue Token (Register doesn't necessarily set device_id immediately unless we add it to RegisterReq)
-    // For now, pass None (Phase 1 behavior) or we can expand    // Issue Token (Register doesn't necessarily set device_id immediately unless we add it to RegisterReq)
-    let token = issue_token(&state.jwt_secret, user_id, req.device_id)?;
+    otp::burn(&mut *tx, &identifier_hash, &req.code).await?;
+
+    tx.commit().await
+        .map_err(|e| { tracing::error!("db commit: {}", e); ApiError::Internal })?;
+
+    if let Some(previous) = previous_holder {
+        state.revocation.forget_user(previous);
+    }
 
-    Ok(Json(AuthResp { user_id, access_token: token }))
+    // Account-level token; the device is bound later via /auth/device/challenge + /verify
+    let resp = issue_session(&state, user_id, None).await?;
+
+    Ok(Json(resp))
+}
+
+pub async fn request_otp(
+    State(state): State<AppState>,
+    Json(req): Json<OtpReq>,
+) -> Result<Json<OtpResp>, ApiError> {
+    let identifier = otp_target(&req.phone, &req.email)?;
+    let identifier_hash = state.identifiers.hash(&identifier);
+
+    let code = otp::issue(&state, &identifier_hash).await?;
+    state.otp_sender.send(identifier.channel(), &identifier.normalized, &code).await?;
+
+    Ok(Json(OtpResp { ok: true, expires_in: otp::OTP_TTL_SECS }))
 }
 
 pub async fn login(
     State(state): State<AppState>,
     Json(req): Json<LoginReq>,
 ) -> Result<Json<AuthResp>, ApiError> {
-    // 1. Verify Code (DEV STUB: Accept ANY code for now, or "000000")
-    if req.code != "000000" {
-        // In real V1, we would check a redis OTP table.
-        // allow bypass for testing.
-        // return Err(ApiError::Unauthorized);
-    }
+    // 1. Verify Code against the identifier it was issued for
+    let identifier = otp_target(&req.phone, &req.email)?;
+    let identifier_hash = state.identifiers.hash(&identifier);
+    otp::verify(&state, &identifier_hash, &req.code).await?;
 
-    // 2. Lookup User
-    let (phone_hash, email_hash) = (
-        req.phone.as_ref().map(|s| hash_identifier(s)),
-        req.email.as_ref().map(|s| hash_identifier(s))
-    );
+    // 2. Lookup User (only by the identifier that was just verified, under any stored hash form)
+    let is_phone = identifier.kind == IdentifierKind::Phone;
+    let candidates = state.identifiers.lookup_hashes(&identifier);
 
     let user = sqlx::query!(
         r#"
-        SELECT id FROM users 
-        WHERE (phone_hash IS NOT NULL AND phone_hash = $1)
-           OR (email_hash IS NOT NULL AND email_hash = $2)
+        SELECT id, phone_hash, email_hash FROM users
+        WHERE ($2 AND phone_hash = ANY($1))
+           OR (NOT $2 AND email_hash = ANY($1))
         LIMIT 1
         "#,
-        phone_hash,
-        email_hash
+        &candidates,
+        is_phone
     )
     .fetch_optional(&state.db)
     .await
     .map_err(|e| { tracing::error!("login user lookup db: {}", e); ApiError::Internal })?;
 
     let user_record = user.ok_or(ApiError::NotFound("User not found".into()))?;
+    ensure_active(&state, user_record.id).await?;
+    reglock::enforce(&state, user_record.id, req.registration_lock.as_deref()).await?;
+
+    // Lazily upgrade legacy / old-pepper hashes to the current pepper
+    let stored = if is_phone { &user_record.phone_hash } else { &user_record.email_hash };
+    if stored.as_deref() != Some(identifier_hash.as_str()) {
+        let res = sqlx::query!(
+            r#"
+            UPDATE users SET
+                phone_hash = CASE WHEN $2 THEN $3 ELSE phone_hash END,
+                email_hash = CASE WHEN $2 THEN email_hash ELSE $3 END,
+                updated_at = now()
+            WHERE id = $1
+            "#,
+            user_record.id,
+            is_phone,
+            identifier_hash
+        )
+        .execute(&state.db)
+        .await;
+        if let Err(e) = res {
+            tracing::error!("identifier rehash: {}", e);
+        }
+    }
 
     // 3. Issue Token
-    let token = issue_token(&state.jwt_secret, user_record.id, req.device_id)?;
+    let resp = issue_session(&state, user_record.id, None).await?;
+
+    Ok(Json(resp))
+}
+
+/// CryptoSpec §7.4 restore: take the account back onto a new device. Every old device,
+/// its keys and sessions are revoked and the identity epoch is bumped so contacts see a
+/// safety number change.
+pub async fn recover(
+    State(state): State<AppState>,
+    Json(req): Json<RecoverReq>,
+) -> Result<Json<RecoverResp>, ApiError> {
+    let identifier = otp_target(&req.phone, &req.email)?;
+    let identifier_hash = state.identifiers.hash(&identifier);
+
+    // Everything that can reject the new device is checked before the old ones are touched,
+    // so a bad request never leaves the account without devices.
+    let new_device = RegisterDeviceReq {
+        device_id: req.device_id,
+        platform: req.platform,
+        identity_key: req.identity_key,
+        push_token: req.push_token,
+    };
+    devices::validate_device_req(&new_device)?;
+
+    let taken = sqlx::query!(r#"SELECT id FROM devices WHERE id = $1"#, new_device.device_id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|e| { tracing::error!("recover device lookup: {}", e); ApiError::Internal })?;
+    if taken.is_some() {
+        return Err(ApiError::Conflict("device_id_taken"));
+    }
+
+    otp::check(&state, &identifier_hash, &req.code).await?;
+
+    let is_phone = identifier.kind == IdentifierKind::Phone;
+    let candidates = state.identifiers.lookup_hashes(&identifier);
+
+    let user = sqlx::query!(
+        r#"
+        SELECT id FROM users
+        WHERE ($2 AND phone_hash = ANY($1)) OR (NOT $2 AND email_hash = ANY($1))
+        LIMIT 1
+        "#,
+        &candidates,
+        is_phone
+    )
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("recover user lookup: {}", e); ApiError::Internal })?
+    .ok_or(ApiError::NotFound("User not found".into()))?;
+
+    // Banned or deactivated accounts can't be recovered onto a fresh device.
+    ensure_active(&state, user.id).await?;
+    reglock::enforce(&state, user.id, req.registration_lock.as_deref()).await?;
+
+    let mut tx = state.db.begin().await
+        .map_err(|e| { tracing::error!("db begin: {}", e); ApiError::Internal })?;
+
+    devices::revoke_all_devices_in(&mut tx, user.id).await?;
+
+    let epoch = sqlx::query!(
+        r#"
+        UPDATE users SET identity_epoch = identity_epoch + 1, updated_at = now()
+        WHERE id = $1
+        RETURNING identity_epoch
+        "#,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| { tracing::error!("recover epoch bump: {}", e); ApiError::Internal })?;
+
+    devices::store_device(&mut tx, state.max_devices_per_user, user.id, &new_device, ExistingDevice::Reject).await?;
+
+    otp::burn(&mut *tx, &identifier_hash, &req.code).await?;
+
+    tx.commit().await
+        .map_err(|e| { tracing::error!("db commit: {}", e); ApiError::Internal })?;
 
-    Ok(Json(AuthResp { user_id: user_record.id, access_token: token }))
+    state.revocation.forget_user(user.id);
+
+    // Account-level token; the new device binds itself via /auth/device/challenge once its keys are up.
+    let auth = issue_session(&state, user.id, None).await?;
+    Ok(Json(RecoverResp { auth, identity_epoch: epoch.identity_epoch }))
+}
+
+pub async fn refresh(
+    State(state): State<AppState>,
+    Json(req): Json<RefreshReq>,
+) -> Result<Json<AuthResp>, ApiError> {
+    let owner = tokens::consume_refresh(&state.db, &req.refresh_token).await?;
+    if let Err(e) = ensure_active(&state, owner.user_id).await {
+        // A banned or deleted account's sessions die here rather than rotating forever.
+        tokens::revoke_family(&state.db, owner.family_id).await?;
+        return Err(e);
+    }
+
+    let access_token = issue_token(&state.keyring, owner.user_id, owner.device_id)?;
+    let refresh_token = tokens::issue_refresh(&state.db, owner.family_id, owner.user_id, owner.device_id).await?;
+
+    Ok(Json(AuthResp {
+        user_id: owner.user_id,
+        access_token,
+        expires_in: tokens::ACCESS_TOKEN_TTL_SECS,
+        refresh_token,
+    }))
+}
+
+pub async fn logout(
+    State(state): State<AppState>,
+    access: Option<Claims>,
+    Json(req): Json<RefreshReq>,
+) -> Result<Json<LogoutResp>, ApiError> {
+    tokens::revoke_by_token(&state.db, &req.refresh_token).await?;
+    // Also kill the presented access token instead of letting it live out its TTL.
+    if let Some(claims) = access {
+        state.revocation.deny_token(&state.db, &claims).await?;
+    }
+    Ok(Json(LogoutResp { ok: true }))
+}
+
+/// Step 1 of device binding: hand out a nonce for the device's identity key to sign.
+pub async fn device_challenge(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(req): Json<DeviceChallengeReq>,
+) -> Result<Json<DeviceChallengeResp>, ApiError> {
+    let challenge = device_auth::create_challenge(&state.db, claims.sub, req.device_id).await?;
+
+    Ok(Json(DeviceChallengeResp {
+        challenge_id: challenge.id,
+        nonce_b64: challenge.nonce_b64,
+        expires_in: device_auth::CHALLENGE_TTL_SECS,
+    }))
+}
+
+/// Step 2 of device binding: on a valid signature, issue a token with `device` set.
+pub async fn device_verify(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(req): Json<DeviceVerifyReq>,
+) -> Result<Json<AuthResp>, ApiError> {
+    let device_id = device_auth::verify_challenge(&state.db, claims.sub, req.challenge_id, &req.signature_b64).await?;
+
+    let resp = issue_session(&state, claims.sub, Some(device_id)).await?;
+    Ok(Json(resp))
+}
+
+/// Rejects users that are banned or no longer active, the same rule the access-token revocation check applies.
+async fn ensure_active(state: &AppState, user_id: Uuid) -> Result<(), ApiError> {
+    let active = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM users WHERE id = $1 AND status = 'active' AND banned_at IS NULL) AS "active!""#,
+        user_id
+    )
+    .fetch_one(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("user status check: {}", e); ApiError::Internal })?;
+
+    if !active {
+        return Err(ApiError::Unauthorized);
+    }
+    Ok(())
+}
+
+/// Starts a new refresh-token family and mints the matching access token.
+pub async fn issue_session(state: &AppState, user_id: Uuid, device_id: Option<Uuid>) -> Result<AuthResp, ApiError> {
+    let access_token = issue_token(&state.keyring, user_id, device_id)?;
+    let refresh_token = tokens::issue_refresh(&state.db, Uuid::new_v4(), user_id, device_id).await?;
+
+    Ok(AuthResp {
+        user_id,
+        access_token,
+        expires_in: tokens::ACCESS_TOKEN_TTL_SECS,
+        refresh_token,
+    })
 }
 
-fn issue_token(secret: &str, user_id: Uuid, device_id: Option<Uuid>) -> Result<String, ApiError> {
-    let exp = (Utc::now() + Duration::days(30)).timestamp() as usize;
-    let claims = Claims { sub: user_id, device: device_id, exp };
+pub fn issue_token(keyring: &Keyring, user_id: Uuid, device_id: Option<Uuid>) -> Result<String, ApiError> {
+    let exp = (Utc::now() + Duration::seconds(tokens::ACCESS_TOKEN_TTL_SECS)).timestamp() as usize;
+    let claims = Claims { sub: user_id, device: device_id, jti: Uuid::new_v4(), exp };
 
-    jsonwebtoken::encode(
-        &Header::new(Algorithm::HS256),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    ).map_err(|_| ApiError::Internal)
+    keyring.sign(&claims)
 }
@@ -1,7 +1,9 @@
-use axum::{routing::post, Router, extract::{State}, Json, http::HeaderMap};
+use axum::{routing::{get, patch, post}, Router, extract::{Path, State}, Json};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
 use uuid::Uuid;
-use crate::{state::AppState, errors::ApiError, auth::require_auth};
+use crate::{state::AppState, errors::ApiError, auth::Claims, key_format::{self, KeyError}};
 
 #[derive(Debug, Deserialize)]
 pub struct RegisterDeviceReq {
@@ -14,16 +16,351 @@ pub struct RegisterDeviceReq {
 #[derive(Debug, Serialize)]
 pub struct RegisterDeviceResp { pub ok: bool }
 
+#[derive(Debug, Serialize)]
+pub struct DeviceInfo {
+    pub device_id: Uuid,
+    pub device_name: Option<String>,
+    pub platform: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub current: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameDeviceReq {
+    pub device_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OkResp { pub ok: bool }
+
+const MAX_DEVICE_NAME_LEN: usize = 64;
+
 pub fn router() -> Router<AppState> {
-    Router::new().route("/devices/register", post(register_device))
+    Router::new()
+        .route("/devices", get(list_devices))
+        .route("/devices/register", post(register_device))
+        .route("/devices/:device_id", patch(rename_device).delete(delete_device))
 }
 
 pub async fn register_device(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    claims: Claims,
     Json(req): Json<RegisterDeviceReq>,
 ) -> Result<Json<RegisterDeviceResp>, ApiError> {
-    let _claims = require_auth(&headers, &state)?;
-    // TODO: insert into DB.
+    upsert_device(&state, claims.sub, req, ExistingDevice::Update { bound_device: claims.device }).await?;
     Ok(Json(RegisterDeviceResp { ok: true }))
 }
+
+/// What `store_device` does when the device_id already exists.
+#[derive(Debug, Clone, Copy)]
+pub enum ExistingDevice {
+    /// `/devices/register`: the caller's own active device is updated. Its identity key only
+    /// changes with a token bound to that device, otherwise any account token could swap it.
+    Update { bound_device: Option<Uuid> },
+    /// Provisioning and recovery: the id must never have been used.
+    Reject,
+}
+
+/// Checks that don't need the database, so callers can run them before any destructive step.
+pub fn validate_device_req(req: &RegisterDeviceReq) -> Result<(), ApiError> {
+    if req.platform != "ios" && req.platform != "android" {
+        return Err(ApiError::BadRequest("platform must be ios or android".into()));
+    }
+    key_format::identity_key(&req.identity_key)?;
+    Ok(())
+}
+
+/// Creates or updates a device for `user_id` in its own transaction.
+pub async fn upsert_device(state: &AppState, user_id: Uuid, req: RegisterDeviceReq, existing: ExistingDevice) -> Result<(), ApiError> {
+    validate_device_req(&req)?;
+
+    let mut tx = state.db.begin().await
+        .map_err(|e| { tracing::error!("db begin: {}", e); ApiError::Internal })?;
+
+    store_device(&mut tx, state.max_devices_per_user, user_id, &req, existing).await?;
+
+    tx.commit().await
+        .map_err(|e| { tracing::error!("db commit: {}", e); ApiError::Internal })?;
+
+    Ok(())
+}
+
+/// Writes a (validated) device inside the caller's transaction, enforcing ownership, identity-key
+/// pinning and the per-user limit. Shared by `/devices/register`, provisioning and recovery.
+pub async fn store_device(
+    conn: &mut PgConnection,
+    max_devices: i64,
+    user_id: Uuid,
+    req: &RegisterDeviceReq,
+    existing_policy: ExistingDevice,
+) -> Result<(), ApiError> {
+    // Serialize concurrent registrations for the same user so the limit can't be raced.
+    sqlx::query!(r#"SELECT id FROM users WHERE id = $1 FOR UPDATE"#, user_id)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| { tracing::error!("device user lock: {}", e); ApiError::Internal })?;
+
+    let existing = sqlx::query!(
+        r#"SELECT user_id, revoked_at, identity_key_b64 FROM devices WHERE id = $1"#,
+        req.device_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| { tracing::error!("device lookup: {}", e); ApiError::Internal })?;
+
+    match (existing, existing_policy) {
+        (Some(_), ExistingDevice::Reject) => return Err(ApiError::Conflict("device_id_taken")),
+        // Device IDs belong to one user forever, and revoked IDs are never reused
+        (Some(d), _) if d.user_id != user_id || d.revoked_at.is_some() => {
+            return Err(ApiError::Unauthorized);
+        }
+        (Some(d), ExistingDevice::Update { bound_device }) => {
+            let changes_identity = d.identity_key_b64.is_some_and(|k| k != req.identity_key);
+            if changes_identity && bound_device != Some(req.device_id) {
+                return Err(KeyError::IdentityMismatch.into());
+            }
+        }
+        (None, _) => {
+            let active = sqlx::query!(
+                r#"SELECT COUNT(*) AS "count!" FROM devices WHERE user_id = $1 AND revoked_at IS NULL"#,
+                user_id
+            )
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(|e| { tracing::error!("device count: {}", e); ApiError::Internal })?;
+
+            if active.count >= max_devices {
+                return Err(ApiError::Conflict("device_limit_reached"));
+            }
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO devices (id, user_id, platform, identity_key_b64)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (id)
+        DO UPDATE SET
+            platform = EXCLUDED.platform,
+            identity_key_b64 = EXCLUDED.identity_key_b64,
+            updated_at = now()
+        "#,
+        req.device_id,
+        user_id,
+        req.platform,
+        req.identity_key
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| { tracing::error!("device upsert: {}", e); ApiError::Internal })?;
+
+    if let Some(push_token) = &req.push_token {
+        sqlx::query!(
+            r#"
+            INSERT INTO push_tokens (user_id, device_id, platform, token)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (device_id)
+            DO UPDATE SET platform = EXCLUDED.platform, token = EXCLUDED.token, created_at = now()
+            "#,
+            user_id,
+            req.device_id,
+            req.platform,
+            push_token
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| { tracing::error!("push token upsert: {}", e); ApiError::Internal })?;
+    }
+
+    Ok(())
+}
+
+pub async fn list_devices(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<DeviceInfo>>, ApiError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, device_name, platform, created_at, last_seen_at
+        FROM devices
+        WHERE user_id = $1 AND revoked_at IS NULL
+        ORDER BY created_at
+        "#,
+        claims.sub
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("device list: {}", e); ApiError::Internal })?;
+
+    let devices = rows.into_iter().map(|d| DeviceInfo {
+        device_id: d.id,
+        device_name: d.device_name,
+        platform: d.platform,
+        created_at: d.created_at,
+        last_seen_at: d.last_seen_at,
+        current: claims.device == Some(d.id),
+    }).collect();
+
+    Ok(Json(devices))
+}
+
+pub async fn rename_device(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<RenameDeviceReq>,
+) -> Result<Json<OkResp>, ApiError> {
+    let name = req.device_name.trim();
+    if name.is_empty() || name.chars().count() > MAX_DEVICE_NAME_LEN {
+        return Err(ApiError::BadRequest("device_name must be 1-64 characters".into()));
+    }
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE devices SET device_name = $1, updated_at = now()
+        WHERE id = $2 AND user_id = $3 AND revoked_at IS NULL
+        "#,
+        name,
+        device_id,
+        claims.sub
+    )
+    .execute(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("device rename: {}", e); ApiError::Internal })?;
+
+    if updated.rows_affected() == 0 {
+        return Err(ApiError::NotFound("Device not found".into()));
+    }
+
+    Ok(Json(OkResp { ok: true }))
+}
+
+pub async fn delete_device(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<OkResp>, ApiError> {
+    if !revoke_device(&state, claims.sub, device_id).await? {
+        return Err(ApiError::NotFound("Device not found".into()));
+    }
+    Ok(Json(OkResp { ok: true }))
+}
+
+/// Revokes a device per CryptoSpec §9.4: marks it revoked, removes its keys so future
+/// bundles exclude it, drops messages still queued for it and kills its sessions.
+/// Returns false if the device doesn't exist, isn't the user's, or was already revoked.
+pub async fn revoke_device(state: &AppState, user_id: Uuid, device_id: Uuid) -> Result<bool, ApiError> {
+    let mut tx = state.db.begin().await
+        .map_err(|e| { tracing::error!("db begin: {}", e); ApiError::Internal })?;
+
+    if !revoke_device_in(&mut tx, user_id, device_id).await? {
+        return Ok(false);
+    }
+
+    tx.commit().await
+        .map_err(|e| { tracing::error!("db commit: {}", e); ApiError::Internal })?;
+
+    // Access tokens for this device fail the revocation check from here on.
+    state.revocation.forget_device(device_id);
+
+    Ok(true)
+}
+
+/// `revoke_device` inside the caller's transaction. The caller clears the revocation
+/// cache once the transaction has committed.
+pub async fn revoke_device_in(conn: &mut PgConnection, user_id: Uuid, device_id: Uuid) -> Result<bool, ApiError> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE devices SET revoked_at = now(), updated_at = now()
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        device_id,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| { tracing::error!("device revoke: {}", e); ApiError::Internal })?;
+
+    if revoked.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query!(r#"DELETE FROM prekey_bundles WHERE device_id = $1"#, device_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| { tracing::error!("revoke bundles: {}", e); ApiError::Internal })?;
+
+    sqlx::query!(r#"DELETE FROM one_time_prekeys WHERE device_id = $1 AND consumed_at IS NULL"#, device_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| { tracing::error!("revoke otks: {}", e); ApiError::Internal })?;
+
+    sqlx::query!(r#"DELETE FROM signed_prekeys WHERE device_id = $1"#, device_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| { tracing::error!("revoke signed prekeys: {}", e); ApiError::Internal })?;
+
+    sqlx::query!(r#"DELETE FROM messages WHERE to_device_id = $1 AND delivered = false"#, device_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| { tracing::error!("revoke messages: {}", e); ApiError::Internal })?;
+
+    sqlx::query!(r#"DELETE FROM push_tokens WHERE device_id = $1"#, device_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| { tracing::error!("revoke push tokens: {}", e); ApiError::Internal })?;
+
+    sqlx::query!(r#"DELETE FROM server_notices WHERE device_id = $1"#, device_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| { tracing::error!("revoke notices: {}", e); ApiError::Internal })?;
+
+    sqlx::query!(
+        r#"UPDATE refresh_tokens SET revoked_at = now() WHERE device_id = $1 AND revoked_at IS NULL"#,
+        device_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| { tracing::error!("revoke refresh tokens: {}", e); ApiError::Internal })?;
+
+    Ok(true)
+}
+
+/// Revokes every active device of a user plus its account-level sessions and access tokens,
+/// inside the caller's transaction. Used when an account is taken over (recovery, re-registration); the caller
+/// calls `forget_user` on the revocation cache after commit.
+pub async fn revoke_all_devices_in(conn: &mut PgConnection, user_id: Uuid) -> Result<(), ApiError> {
+    let active = sqlx::query!(
+        r#"SELECT id FROM devices WHERE user_id = $1 AND revoked_at IS NULL"#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| { tracing::error!("device list for revoke: {}", e); ApiError::Internal })?;
+
+    for d in active {
+        revoke_device_in(&mut *conn, user_id, d.id).await?;
+    }
+
+    // Account-level sessions too, not just the device-bound ones revoke_device_in covers
+    sqlx::query!(
+        r#"UPDATE refresh_tokens SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL"#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| { tracing::error!("revoke user sessions: {}", e); ApiError::Internal })?;
+
+    // Access tokens aren't tracked individually; everything issued up to now stops passing
+    // `RevocationCache::is_revoked`.
+    sqlx::query!(
+        r#"UPDATE users SET access_tokens_valid_after = date_trunc('second', now()) WHERE id = $1"#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| { tracing::error!("revoke user access tokens: {}", e); ApiError::Internal })?;
+
+    Ok(())
+}
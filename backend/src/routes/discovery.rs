@@ -0,0 +1,137 @@
+use axum::{routing::post, Router, extract::State, Json};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::{state::AppState, errors::ApiError, auth::Claims};
+
+// Each account gets a bucket of DISCOVERY_BUCKET_CAPACITY lookups that refills over a day,
+// which bounds how much of the identifier space one account can probe.
+const DISCOVERY_BUCKET_CAPACITY: f64 = 2000.0;
+const DISCOVERY_REFILL_PER_SEC: f64 = DISCOVERY_BUCKET_CAPACITY / 86_400.0;
+const MAX_HASHES_PER_REQUEST: usize = 500;
+
+/// `hashes` are hex `Identifier::prehash` values: SHA-256 of `phone:+<E.164>` or
+/// `email:<lowercased address>`. They are never logged or stored (LoggingPolicy §2).
+#[derive(Deserialize)]
+pub struct DiscoveryReq {
+    pub hashes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiscoveryMatch {
+    pub hash: String,
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiscoveryResp {
+    pub matches: Vec<DiscoveryMatch>,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/discovery/contacts", post(discover_contacts))
+}
+
+/// Takes `cost` tokens from the caller's bucket, or fails with 429 leaving it untouched.
+async fn take_tokens(state: &AppState, user_id: Uuid, cost: f64) -> Result<(), ApiError> {
+    let taken = sqlx::query!(
+        r#"
+        INSERT INTO discovery_buckets (user_id, tokens, updated_at)
+        VALUES ($1, $2 - $3, now())
+        ON CONFLICT (user_id) DO UPDATE SET
+            tokens = LEAST($2, discovery_buckets.tokens
+                + EXTRACT(EPOCH FROM now() - discovery_buckets.updated_at)::float8 * $4) - $3,
+            updated_at = now()
+        WHERE LEAST($2, discovery_buckets.tokens
+                + EXTRACT(EPOCH FROM now() - discovery_buckets.updated_at)::float8 * $4) >= $3
+        RETURNING tokens
+        "#,
+        user_id,
+        DISCOVERY_BUCKET_CAPACITY,
+        cost,
+        DISCOVERY_REFILL_PER_SEC
+    )
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("discovery bucket: {}", e); ApiError::Internal })?;
+
+    if taken.is_some() {
+        return Ok(());
+    }
+
+    let available = sqlx::query_scalar!(
+        r#"
+        SELECT LEAST($2, tokens + EXTRACT(EPOCH FROM now() - updated_at)::float8 * $3) AS "tokens!"
+        FROM discovery_buckets WHERE user_id = $1
+        "#,
+        user_id,
+        DISCOVERY_BUCKET_CAPACITY,
+        DISCOVERY_REFILL_PER_SEC
+    )
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("discovery bucket: {}", e); ApiError::Internal })?
+    .unwrap_or(0.0);
+
+    tracing::warn!("rate limit exceeded: contact_discovery");
+    let wait = ((cost - available).max(0.0) / DISCOVERY_REFILL_PER_SEC).ceil() as u64;
+    Err(ApiError::RateLimited(wait.max(1)))
+}
+
+pub async fn discover_contacts(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(req): Json<DiscoveryReq>,
+) -> Result<Json<DiscoveryResp>, ApiError> {
+    if req.hashes.is_empty() || req.hashes.len() > MAX_HASHES_PER_REQUEST {
+        return Err(ApiError::BadRequest("hashes must contain 1-500 entries".into()));
+    }
+
+    let mut prehashes = Vec::with_capacity(req.hashes.len());
+    for h in &req.hashes {
+        let bytes = hex::decode(h).ok().filter(|b| b.len() == 32)
+            .ok_or(ApiError::BadRequest("hashes must be hex SHA-256".into()))?;
+        prehashes.push(bytes);
+    }
+    prehashes.sort();
+    prehashes.dedup();
+
+    take_tokens(&state, claims.sub, prehashes.len() as f64).await?;
+
+    // stored hash -> submitted hash
+    let mut lookup = std::collections::HashMap::new();
+    for prehash in &prehashes {
+        for stored in state.identifiers.hashes_for_prehash(prehash) {
+            lookup.insert(stored, hex::encode(prehash));
+        }
+    }
+    let stored: Vec<String> = lookup.keys().cloned().collect();
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT u.id, u.phone_hash, u.email_hash FROM users u
+        WHERE (u.phone_hash = ANY($1) OR u.email_hash = ANY($1))
+          AND u.status = 'active'
+          AND u.id <> $2
+          AND NOT EXISTS(
+              SELECT 1 FROM blocked_users b
+              WHERE b.blocker_user_id = u.id AND b.blocked_user_id = $2
+          )
+        "#,
+        &stored,
+        claims.sub
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("discovery lookup: {}", e); ApiError::Internal })?;
+
+    let mut matches = Vec::new();
+    for row in rows {
+        for h in [row.phone_hash, row.email_hash].into_iter().flatten() {
+            if let Some(submitted) = lookup.get(&h) {
+                matches.push(DiscoveryMatch { hash: submitted.clone(), user_id: row.id });
+            }
+        }
+    }
+
+    Ok(Json(DiscoveryResp { matches }))
+}
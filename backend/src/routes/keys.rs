@@ -1,19 +1,32 @@
-use axum::{routing::{get, post}, Router, extract::{Path, State}, Json, http::HeaderMap};
+use std::collections::HashSet;
+use axum::{routing::{delete, get, post}, Router, extract::{Path, Query, State}, Json};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
 use uuid::Uuid;
-use crate::{state::AppState, errors::ApiError, auth::require_auth};
+use crate::{state::AppState, errors::ApiError, auth::Claims, key_format::{self, KeyError}};
 
+const MAX_OTKS_PER_UPLOAD: usize = 100;
+const MAX_OTKS_PER_DEVICE: i64 = 500; // unconsumed keys held for one device
+const SIGNED_PREKEY_MAX_AGE_DAYS: i64 = 30; // CryptoSpec §9.2
+const MAX_KNOWN_DEVICES: usize = 100;
+
+/// `kind` of the server notice queued when a device's pool drops below `OTK_LOW_WATERMARK`.
+pub const PREKEYS_LOW_NOTICE: &str = "prekeys_low";
+
+// Key IDs are client-assigned and non-negative; initiators echo them so the recipient
+// knows which prekeys a session used (CryptoSpec §4.3).
 #[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
 pub struct SignedPrekey {
     pub key_id: i32,
-    pub public_key: String,
-    pub signature: String,
+    pub public_key: String, // base64 X25519
+    pub signature: String, // base64 Ed25519 by the identity key
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OneTimePrekey {
     pub key_id: i32,
-    pub public_key: String,
+    pub public_key: String, // base64 X25519
 }
 
 #[derive(Debug, Deserialize)]
@@ -22,13 +35,118 @@ pub struct UploadBundleReq {
     pub device_id: Uuid,
     pub identity_key_ed25519_b64: String,
     pub static_x25519_b64: String,
-    pub signed_prekey_x25519_b64: String,
-    pub signed_prekey_signature_b64: String,
+    pub signed_prekey: Option<SignedPrekey>, // required unless sent in the legacy fields
+    #[serde(default)]
+    pub one_time_prekeys: Vec<OneTimePrekey>,
+    // Omit to keep the stored one (dropped if the identity key changes).
+    pub last_resort_prekey: Option<SignedPrekey>,
+    #[serde(flatten)]
+    pub legacy: LegacyPrekeyFields,
+}
+
+/// The upload fields from before prekey IDs, still accepted while clients move over.
+/// Keys sent this way are numbered by the server after the device's highest stored ID.
+#[derive(Debug, Default, Deserialize)]
+pub struct LegacyPrekeyFields {
+    pub signed_prekey_x25519_b64: Option<String>,
+    pub signed_prekey_signature_b64: Option<String>,
+    #[serde(default)]
     pub one_time_prekeys_b64: Vec<String>,
 }
 
+/// An uploaded prekey before `assign_key_ids`: `key_id` is `None` if it came in `LegacyPrekeyFields`.
+struct PendingSignedPrekey {
+    key_id: Option<i32>,
+    public_key: String,
+    signature: String,
+}
+
+struct PendingOneTimePrekey {
+    key_id: Option<i32>,
+    public_key: String,
+}
+
+impl UploadBundleReq {
+    /// Merges the legacy fields into the ID'd ones. Negative IDs in the ID'd fields are refused
+    /// for the signed and last-resort prekeys and left for the caller to drop for one-time prekeys.
+    fn take_prekeys(&mut self) -> Result<(PendingSignedPrekey, Option<SignedPrekey>, Vec<PendingOneTimePrekey>), ApiError> {
+        let legacy = std::mem::take(&mut self.legacy);
+
+        let signed_prekey = match (self.signed_prekey.take(), legacy.signed_prekey_x25519_b64, legacy.signed_prekey_signature_b64) {
+            (Some(spk), None, None) => PendingSignedPrekey { key_id: Some(spk.key_id), public_key: spk.public_key, signature: spk.signature },
+            (None, Some(public_key), Some(signature)) => PendingSignedPrekey { key_id: None, public_key, signature },
+            _ => return Err(ApiError::BadRequest("send exactly one signed_prekey".into())),
+        };
+        let last_resort = self.last_resort_prekey.take();
+        if signed_prekey.key_id.is_some_and(|id| id < 0) || last_resort.as_ref().is_some_and(|k| k.key_id < 0) {
+            return Err(ApiError::BadRequest("key_id must be non-negative".into()));
+        }
+
+        let one_time = std::mem::take(&mut self.one_time_prekeys).into_iter()
+            .map(|k| PendingOneTimePrekey { key_id: Some(k.key_id), public_key: k.public_key })
+            .chain(legacy.one_time_prekeys_b64.into_iter()
+                .map(|public_key| PendingOneTimePrekey { key_id: None, public_key }))
+            .collect();
+
+        Ok((signed_prekey, last_resort, one_time))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BundleQuery {
+    pub known_devices: Option<String>, // comma-separated device ids the sender already has sessions with
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RotateSignedPrekeyReq {
+    pub device_id: Uuid,
+    pub signed_prekey: SignedPrekey,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OkResp { pub ok: bool }
+
+#[derive(Debug, Deserialize)]
+pub struct DeletePrekeysReq {
+    pub key_ids: Vec<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeletePrekeysResp { pub deleted: u64 }
+
+#[derive(Debug, Serialize)]
+pub struct UploadBundleResp {
+    pub ok: bool,
+    pub accepted: usize,
+    pub rejected: usize, // malformed, duplicate key or key_id, or over the per-device limit
+    pub signed_prekey_id: i32,
+    // IDs given to accepted keys from `one_time_prekeys_b64`; ID'd keys keep the client's.
+    pub assigned_one_time_prekeys: Vec<OneTimePrekey>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DevicePrekeyStatus {
+    pub device_id: Uuid,
+    pub one_time_prekeys: i64, // unconsumed
+    pub signed_prekey_id: Option<i32>,
+    pub signed_prekey_created_at: Option<DateTime<Utc>>,
+    pub signed_prekey_stale: bool, // older than SIGNED_PREKEY_MAX_AGE_DAYS (or missing): rotate it
+}
+
 #[derive(Debug, Serialize)]
-pub struct UploadBundleResp { pub ok: bool }
+pub struct PrekeyStatusResp {
+    pub low_watermark: i64,
+    pub devices: Vec<DevicePrekeyStatus>,
+}
+
+/// Which key `PrekeyBundleResp::one_time_prekey` holds.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrekeyKind {
+    OneTime,
+    LastResort, // pool was empty; the recipient should refill it
+    None,
+}
 
 #[derive(Debug, Serialize)]
 pub struct PrekeyBundleResp {
@@ -36,6 +154,12 @@ pub struct PrekeyBundleResp {
     pub device_id: Uuid,
     pub identity_key_ed25519_b64: String,
     pub static_x25519_b64: String,
+    pub signed_prekey: SignedPrekey,
+    pub one_time_prekey: Option<OneTimePrekey>,
+    pub one_time_prekey_kind: PrekeyKind,
+    pub last_resort_prekey_signature_b64: Option<String>, // set when the kind is last_resort
+    pub identity_epoch: i32, // bumped on account recovery; a change means re-verify the safety number
+    // Pre-ID copies of `signed_prekey` / `one_time_prekey` for clients that haven't moved over yet.
     pub signed_prekey_x25519_b64: String,
     pub signed_prekey_signature_b64: String,
     pub one_time_prekey_b64: Option<String>,
@@ -45,76 +169,364 @@ pub fn router() -> Router<AppState> {
     Router::new()
         .route("/keys/upload", post(upload_bundle))
         .route("/keys/bundle/:user_id", get(fetch_bundle))
+        .route("/keys/bundle/:user_id/:device_id", get(fetch_device_bundle))
+        .route("/keys/status", get(prekey_status))
+        .route("/keys/signed-prekey", post(rotate_signed_prekey))
+        .route("/keys/one-time/:device_id", delete(delete_one_time_prekeys))
 }
 
 pub async fn upload_bundle(
     State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(req): Json<UploadBundleReq>,
+    claims: Claims,
+    Json(mut req): Json<UploadBundleReq>,
 ) -> Result<Json<UploadBundleResp>, ApiError> {
-    let claims = require_auth(&headers, &state)?;
     // Ensure user requesting upload matches token
     if claims.sub != req.user_id { return Err(ApiError::Unauthorized); }
 
+    let (signed_prekey, last_resort_prekey, one_time_prekeys) = req.take_prekeys()?;
+
+    if one_time_prekeys.len() > MAX_OTKS_PER_UPLOAD {
+        return Err(ApiError::BadRequest(format!("at most {} one-time prekeys per upload", MAX_OTKS_PER_UPLOAD)));
+    }
+    key_format::public_key(&req.static_x25519_b64)?;
+    // A bundle whose signature doesn't verify would fail X3DH for every sender.
+    key_format::verify_signed_prekey(&req.identity_key_ed25519_b64, &signed_prekey.public_key, &signed_prekey.signature)?;
+    if let Some(lr) = &last_resort_prekey {
+        key_format::verify_signed_prekey(&req.identity_key_ed25519_b64, &lr.public_key, &lr.signature)?;
+    }
+
+    // Malformed keys and in-request duplicates (by key or key_id) are dropped, not fatal
+    let submitted = one_time_prekeys.len();
+    let mut seen_keys = HashSet::new();
+    let mut seen_ids = HashSet::new();
+    let candidates: Vec<PendingOneTimePrekey> = one_time_prekeys
+        .into_iter()
+        .filter(|k| {
+            k.key_id.is_none_or(|id| id >= 0)
+                && key_format::public_key(&k.public_key).is_ok()
+                && seen_keys.insert(k.public_key.clone())
+                && k.key_id.is_none_or(|id| seen_ids.insert(id))
+        })
+        .collect();
+    let legacy_keys: HashSet<String> = candidates.iter()
+        .filter(|k| k.key_id.is_none())
+        .map(|k| k.public_key.clone())
+        .collect();
+
+    let mut tx = state.db.begin().await
+        .map_err(|e| { tracing::error!("db begin: {}", e); ApiError::Internal })?;
+
+    // Locking the device row serializes concurrent uploads so the per-device limit holds.
+    let device = sqlx::query!(
+        r#"SELECT revoked_at, identity_key_b64, one_time_prekey_id_floor FROM devices WHERE id = $1 AND user_id = $2 FOR UPDATE"#,
+        req.device_id,
+        claims.sub
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| { tracing::error!("upload device lookup: {}", e); ApiError::Internal })?;
+
+    let device = match device {
+        Some(d) if d.revoked_at.is_none() => d,
+        _ => return Err(ApiError::Unauthorized),
+    };
+    // The bundle must be signed by the identity the device registered with.
+    if device.identity_key_b64.is_some_and(|k| k != req.identity_key_ed25519_b64) {
+        return Err(KeyError::IdentityMismatch.into());
+    }
+
+    let (signed_prekey, candidates) = assign_key_ids(&mut tx, req.device_id, signed_prekey, candidates).await?;
+
     // Upsert Identity/Signed Prekey Bundle
     sqlx::query!(
         r#"
         INSERT INTO prekey_bundles 
         (user_id, device_id, identity_key_ed25519_b64, 
-         signed_prekey_x25519_b64, signed_prekey_signature_b64, static_x25519_b64)
-        VALUES ($1, $2, $3, $4, $5, $6)
+         signed_prekey_id, signed_prekey_x25519_b64, signed_prekey_signature_b64, static_x25519_b64,
+         last_resort_prekey_id, last_resort_prekey_x25519_b64, last_resort_prekey_signature_b64)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
         ON CONFLICT (user_id, device_id) 
         DO UPDATE SET 
-            identity_key_ed25519_b64 = EXCLUDED.identity_key_ed25519_b64,
+            signed_prekey_id = EXCLUDED.signed_prekey_id,
             signed_prekey_x25519_b64 = EXCLUDED.signed_prekey_x25519_b64,
             signed_prekey_signature_b64 = EXCLUDED.signed_prekey_signature_b64,
             static_x25519_b64 = EXCLUDED.static_x25519_b64,
+            last_resort_prekey_id = CASE
+                WHEN EXCLUDED.last_resort_prekey_x25519_b64 IS NOT NULL THEN EXCLUDED.last_resort_prekey_id
+                WHEN prekey_bundles.identity_key_ed25519_b64 = EXCLUDED.identity_key_ed25519_b64 THEN prekey_bundles.last_resort_prekey_id
+            END,
+            last_resort_prekey_x25519_b64 = CASE
+                WHEN EXCLUDED.last_resort_prekey_x25519_b64 IS NOT NULL THEN EXCLUDED.last_resort_prekey_x25519_b64
+                WHEN prekey_bundles.identity_key_ed25519_b64 = EXCLUDED.identity_key_ed25519_b64 THEN prekey_bundles.last_resort_prekey_x25519_b64
+            END,
+            last_resort_prekey_signature_b64 = CASE
+                WHEN EXCLUDED.last_resort_prekey_x25519_b64 IS NOT NULL THEN EXCLUDED.last_resort_prekey_signature_b64
+                WHEN prekey_bundles.identity_key_ed25519_b64 = EXCLUDED.identity_key_ed25519_b64 THEN prekey_bundles.last_resort_prekey_signature_b64
+            END,
+            identity_key_ed25519_b64 = EXCLUDED.identity_key_ed25519_b64,
             created_at = now()
         "#,
         claims.sub,
         req.device_id,
         req.identity_key_ed25519_b64,
-        req.signed_prekey_x25519_b64,
-        req.signed_prekey_signature_b64,
-        req.static_x25519_b64
+        signed_prekey.key_id,
+        signed_prekey.public_key,
+        signed_prekey.signature,
+        req.static_x25519_b64,
+        last_resort_prekey.as_ref().map(|k| k.key_id),
+        last_resort_prekey.as_ref().map(|k| k.public_key.as_str()),
+        last_resort_prekey.as_ref().map(|k| k.signature.as_str())
     )
-    .execute(&state.db)
+    .execute(&mut *tx)
     .await.map_err(|e| { tracing::error!("db: {}", e); ApiError::Internal })?;
 
-    // Insert OTKs (Bulk insert or loop)
-    for key_b64 in req.one_time_prekeys_b64 {
-        sqlx::query!(
-            r#"
-            INSERT INTO one_time_prekeys (user_id, device_id, prekey_b64)
-            VALUES ($1, $2, $3)
-            "#,
-            claims.sub,
-            req.device_id,
-            key_b64
+    record_signed_prekey(&mut tx, claims.sub, req.device_id, &signed_prekey).await?;
+
+    let pooled = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!" FROM one_time_prekeys WHERE device_id = $1 AND consumed_at IS NULL"#,
+        req.device_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| { tracing::error!("otk count: {}", e); ApiError::Internal })?;
+
+    let room = (MAX_OTKS_PER_DEVICE - pooled).max(0) as usize;
+    let (ids, keys): (Vec<i32>, Vec<String>) = candidates.into_iter()
+        .take(room)
+        .map(|k| (k.key_id, k.public_key))
+        .unzip();
+
+    // Keys or key_ids already stored for this device (consumed or not) hit a unique index and are skipped,
+    // as are keys and key_ids whose consumed row was garbage-collected, and key_ids at or below the device's floor.
+    let inserted = sqlx::query_as!(
+        OneTimePrekey,
+        r#"
+        INSERT INTO one_time_prekeys (user_id, device_id, key_id, prekey_x25519_b64)
+        SELECT $1, $2, k.key_id, k.prekey FROM UNNEST($3::int4[], $4::text[]) AS k(key_id, prekey)
+        WHERE k.key_id > $5 AND NOT EXISTS (
+            SELECT 1 FROM one_time_prekey_tombstones t
+            WHERE t.device_id = $2
+              AND (t.key_sha256 = sha256(convert_to(k.prekey, 'UTF8')) OR t.key_id = k.key_id)
         )
-        .execute(&state.db)
-        .await.ok(); // ignore dupes
+        ON CONFLICT DO NOTHING
+        RETURNING key_id, prekey_x25519_b64 AS public_key
+        "#,
+        claims.sub,
+        req.device_id,
+        &ids,
+        &keys,
+        device.one_time_prekey_id_floor.unwrap_or(-1)
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|e| { tracing::error!("otk insert: {}", e); ApiError::Internal })?;
+    let accepted = inserted.len();
+
+    // Re-arm the low-pool notice once the pool is back above the watermark.
+    sqlx::query!(
+        r#"
+        UPDATE devices SET prekeys_low_signaled_at = NULL
+        WHERE id = $1 AND prekeys_low_signaled_at IS NOT NULL
+          AND (SELECT count(*) FROM one_time_prekeys WHERE device_id = $1 AND consumed_at IS NULL) >= $2
+        "#,
+        req.device_id,
+        state.otk_low_watermark
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| { tracing::error!("prekey signal reset: {}", e); ApiError::Internal })?;
+
+    tx.commit().await
+        .map_err(|e| { tracing::error!("db commit: {}", e); ApiError::Internal })?;
+
+    let assigned_one_time_prekeys = inserted.into_iter()
+        .filter(|k| legacy_keys.contains(&k.public_key))
+        .collect();
+
+    Ok(Json(UploadBundleResp {
+        ok: true,
+        accepted,
+        rejected: submitted - accepted,
+        signed_prekey_id: signed_prekey.key_id,
+        assigned_one_time_prekeys,
+    }))
+}
+
+/// Gives keys uploaded through `LegacyPrekeyFields` real IDs. A signed prekey that is already
+/// stored keeps its ID, so re-uploading an unchanged legacy bundle is a no-op. One-time prekeys
+/// are numbered past every ID the device has used, including pruned ones.
+async fn assign_key_ids(
+    conn: &mut PgConnection,
+    device_id: Uuid,
+    signed_prekey: PendingSignedPrekey,
+    one_time_prekeys: Vec<PendingOneTimePrekey>,
+) -> Result<(SignedPrekey, Vec<OneTimePrekey>), ApiError> {
+    let PendingSignedPrekey { key_id, public_key, signature } = signed_prekey;
+    if let Some(key_id) = key_id.filter(|_| one_time_prekeys.iter().all(|k| k.key_id.is_some())) {
+        let one_time_prekeys = one_time_prekeys.into_iter()
+            .filter_map(|k| k.key_id.map(|key_id| OneTimePrekey { key_id, public_key: k.public_key }))
+            .collect();
+        return Ok((SignedPrekey { key_id, public_key, signature }, one_time_prekeys));
     }
 
-    Ok(Json(UploadBundleResp { ok: true }))
+    let stored = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT key_id FROM signed_prekeys WHERE device_id = $1 AND public_key_b64 = $2 LIMIT 1) AS signed_same,
+            (SELECT max(key_id) FROM signed_prekeys WHERE device_id = $1) AS signed_max,
+            GREATEST(
+                (SELECT max(key_id) FROM one_time_prekeys WHERE device_id = $1),
+                (SELECT max(key_id) FROM one_time_prekey_tombstones WHERE device_id = $1),
+                (SELECT one_time_prekey_id_floor FROM devices WHERE id = $1)
+            ) AS one_time_max
+        "#,
+        device_id,
+        public_key
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| { tracing::error!("prekey id lookup: {}", e); ApiError::Internal })?;
+
+    let next = |max: Option<i32>| max.map_or(0, |m| m.saturating_add(1));
+
+    let key_id = key_id.or(stored.signed_same).unwrap_or_else(|| next(stored.signed_max));
+
+    let mut next_one_time = next(stored.one_time_max);
+    let one_time_prekeys = one_time_prekeys.into_iter()
+        .map(|k| {
+            let key_id = k.key_id.unwrap_or_else(|| {
+                let id = next_one_time;
+                next_one_time = next_one_time.saturating_add(1);
+                id
+            });
+            OneTimePrekey { key_id, public_key: k.public_key }
+        })
+        .collect();
+
+    Ok((SignedPrekey { key_id, public_key, signature }, one_time_prekeys))
 }
 
-pub async fn fetch_bundle(
+/// Makes `spk` the device's current signed prekey in the history table, superseding the previous one.
+/// Re-sending the current key is a no-op; reusing a retained key_id for a different key is a conflict.
+async fn record_signed_prekey(conn: &mut PgConnection, user_id: Uuid, device_id: Uuid, spk: &SignedPrekey) -> Result<(), ApiError> {
+    let stored = sqlx::query!(
+        r#"
+        INSERT INTO signed_prekeys (device_id, user_id, key_id, public_key_b64, signature_b64)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (device_id, key_id) DO UPDATE SET key_id = EXCLUDED.key_id
+        RETURNING public_key_b64
+        "#,
+        device_id,
+        user_id,
+        spk.key_id,
+        spk.public_key,
+        spk.signature
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| { tracing::error!("signed prekey insert: {}", e); ApiError::Internal })?;
+
+    if stored.public_key_b64 != spk.public_key {
+        return Err(ApiError::Conflict("signed_prekey_id_taken"));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE signed_prekeys
+        SET superseded_at = CASE WHEN key_id = $2 THEN NULL ELSE now() END
+        WHERE device_id = $1 AND (superseded_at IS NULL OR key_id = $2)
+        "#,
+        device_id,
+        spk.key_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| { tracing::error!("signed prekey supersede: {}", e); ApiError::Internal })?;
+
+    Ok(())
+}
+
+/// Replaces the device's signed prekey without re-uploading the bundle. The previous key
+/// stays in `signed_prekeys` for `SIGNED_PREKEY_GRACE_DAYS`.
+pub async fn rotate_signed_prekey(
     State(state): State<AppState>,
-    headers: HeaderMap,
-    Path(user_id): Path<Uuid>,
-) -> Result<Json<Vec<PrekeyBundleResp>>, ApiError> {
-    let _claims = require_auth(&headers, &state)?;
+    claims: Claims,
+    Json(req): Json<RotateSignedPrekeyReq>,
+) -> Result<Json<OkResp>, ApiError> {
+    if req.signed_prekey.key_id < 0 {
+        return Err(ApiError::BadRequest("key_id must be non-negative".into()));
+    }
+
+    let mut tx = state.db.begin().await
+        .map_err(|e| { tracing::error!("db begin: {}", e); ApiError::Internal })?;
+
+    let bundle = sqlx::query!(
+        r#"
+        SELECT b.identity_key_ed25519_b64
+        FROM prekey_bundles b
+        JOIN devices d ON d.id = b.device_id
+        WHERE b.user_id = $1 AND b.device_id = $2 AND d.revoked_at IS NULL
+        FOR UPDATE OF b
+        "#,
+        claims.sub,
+        req.device_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| { tracing::error!("rotate bundle lookup: {}", e); ApiError::Internal })?
+    .ok_or(ApiError::NotFound("No prekey bundle for device".into()))?;
+
+    key_format::verify_signed_prekey(
+        &bundle.identity_key_ed25519_b64,
+        &req.signed_prekey.public_key,
+        &req.signed_prekey.signature,
+    )?;
+
+    record_signed_prekey(&mut tx, claims.sub, req.device_id, &req.signed_prekey).await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE prekey_bundles
+        SET signed_prekey_id = $2, signed_prekey_x25519_b64 = $3, signed_prekey_signature_b64 = $4
+        WHERE device_id = $1
+        "#,
+        req.device_id,
+        req.signed_prekey.key_id,
+        req.signed_prekey.public_key,
+        req.signed_prekey.signature
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| { tracing::error!("rotate bundle update: {}", e); ApiError::Internal })?;
 
-    // Fetch bundles for ALL devices of the user
+    tx.commit().await
+        .map_err(|e| { tracing::error!("db commit: {}", e); ApiError::Internal })?;
+
+    Ok(Json(OkResp { ok: true }))
+}
+
+/// Bundles of every active device of `user_id` (or just `device_id`), skipping devices in `known`.
+/// Pops one one-time prekey per returned device.
+async fn load_bundles(state: &AppState, user_id: Uuid, device_id: Option<Uuid>, known: &[Uuid]) -> Result<Vec<PrekeyBundleResp>, ApiError> {
+    // Revoked devices never get bundles out (CryptoSpec §9.4), even if a row survived revocation.
     let bundles = sqlx::query!(
         r#"
-        SELECT user_id, device_id, identity_key_ed25519_b64, 
-               static_x25519_b64, signed_prekey_x25519_b64, signed_prekey_signature_b64 
-        FROM prekey_bundles 
-        WHERE user_id = $1
+        SELECT b.user_id, b.device_id, b.identity_key_ed25519_b64,
+               b.static_x25519_b64, b.signed_prekey_id, b.signed_prekey_x25519_b64, b.signed_prekey_signature_b64,
+               b.last_resort_prekey_id, b.last_resort_prekey_x25519_b64, b.last_resort_prekey_signature_b64,
+               u.identity_epoch
+        FROM prekey_bundles b
+        JOIN users u ON u.id = b.user_id
+        JOIN devices d ON d.id = b.device_id
+        WHERE b.user_id = $1
+          AND d.revoked_at IS NULL
+          AND ($2::uuid IS NULL OR b.device_id = $2)
+          AND NOT (b.device_id = ANY($3))
+        ORDER BY d.created_at
         "#,
-        user_id
+        user_id,
+        device_id,
+        known
     )
     .fetch_all(&state.db)
     .await.map_err(|e| { tracing::error!("db bundles fetch: {}", e); ApiError::Internal })?;
@@ -126,7 +538,7 @@ pub async fn fetch_bundle(
         let otk = sqlx::query!(
             r#"
             WITH popped AS (
-                SELECT id, prekey_b64 
+                SELECT id, key_id, prekey_x25519_b64
                 FROM one_time_prekeys 
                 WHERE user_id = $1 AND device_id = $2 AND consumed_at IS NULL
                 LIMIT 1
@@ -136,7 +548,7 @@ pub async fn fetch_bundle(
             SET consumed_at = now() 
             FROM popped 
             WHERE one_time_prekeys.id = popped.id
-            RETURNING popped.prekey_b64
+            RETURNING popped.key_id, popped.prekey_x25519_b64
             "#,
             user_id,
             b.device_id
@@ -144,16 +556,168 @@ pub async fn fetch_bundle(
         .fetch_optional(&state.db)
         .await.map_err(|e| { tracing::error!("db otk pop: {}", e); ApiError::Internal })?;
 
+        signal_if_low(state, user_id, b.device_id).await?;
+
+        // An empty pool falls back to the last-resort key, which stays in place for the next sender.
+        let (prekey, kind, last_resort_sig) = match (otk, b.last_resort_prekey_id, b.last_resort_prekey_x25519_b64) {
+            (Some(o), _, _) => (
+                Some(OneTimePrekey { key_id: o.key_id, public_key: o.prekey_x25519_b64 }),
+                PrekeyKind::OneTime,
+                None,
+            ),
+            (None, Some(key_id), Some(public_key)) => (
+                Some(OneTimePrekey { key_id, public_key }),
+                PrekeyKind::LastResort,
+                b.last_resort_prekey_signature_b64,
+            ),
+            _ => (None, PrekeyKind::None, None),
+        };
+
         results.push(PrekeyBundleResp {
             user_id,
             device_id: b.device_id,
             identity_key_ed25519_b64: b.identity_key_ed25519_b64,
             static_x25519_b64: b.static_x25519_b64,
-            signed_prekey_x25519_b64: b.signed_prekey_x25519_b64,
-            signed_prekey_signature_b64: b.signed_prekey_signature_b64,
-            one_time_prekey_b64: otk.map(|o| o.prekey_b64),
+            signed_prekey_x25519_b64: b.signed_prekey_x25519_b64.clone(),
+            signed_prekey_signature_b64: b.signed_prekey_signature_b64.clone(),
+            one_time_prekey_b64: prekey.as_ref().map(|k| k.public_key.clone()),
+            signed_prekey: SignedPrekey {
+                key_id: b.signed_prekey_id,
+                public_key: b.signed_prekey_x25519_b64,
+                signature: b.signed_prekey_signature_b64,
+            },
+            one_time_prekey: prekey,
+            one_time_prekey_kind: kind,
+            last_resort_prekey_signature_b64: last_resort_sig,
+            identity_epoch: b.identity_epoch,
         });
     }
 
-    Ok(Json(results))
+    Ok(results)
+}
+
+/// All active devices' bundles, minus those listed in `known_devices` so a sender that
+/// already has sessions doesn't burn their one-time prekeys.
+pub async fn fetch_bundle(
+    State(state): State<AppState>,
+    _claims: Claims,
+    Path(user_id): Path<Uuid>,
+    Query(q): Query<BundleQuery>,
+) -> Result<Json<Vec<PrekeyBundleResp>>, ApiError> {
+    let known = q.known_devices
+        .as_deref()
+        .filter(|s| !s.is_empty())
+        .map(|s| s.split(',').map(|d| Uuid::parse_str(d.trim())).collect::<Result<Vec<_>, _>>())
+        .transpose()
+        .map_err(|_| ApiError::BadRequest("known_devices must be comma-separated device ids".into()))?
+        .unwrap_or_default();
+    if known.len() > MAX_KNOWN_DEVICES {
+        return Err(ApiError::BadRequest(format!("at most {} known_devices", MAX_KNOWN_DEVICES)));
+    }
+
+    Ok(Json(load_bundles(&state, user_id, None, &known).await?))
+}
+
+pub async fn fetch_device_bundle(
+    State(state): State<AppState>,
+    _claims: Claims,
+    Path((user_id, device_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<PrekeyBundleResp>, ApiError> {
+    load_bundles(&state, user_id, Some(device_id), &[])
+        .await?
+        .pop()
+        .map(Json)
+        .ok_or(ApiError::NotFound("No prekey bundle for device".into()))
+}
+
+/// Queues a `PREKEYS_LOW_NOTICE` for the device once per drop below the watermark.
+async fn signal_if_low(state: &AppState, user_id: Uuid, device_id: Uuid) -> Result<(), ApiError> {
+    sqlx::query!(
+        r#"
+        WITH low AS (
+            UPDATE devices SET prekeys_low_signaled_at = now()
+            WHERE id = $2 AND revoked_at IS NULL AND prekeys_low_signaled_at IS NULL
+              AND (SELECT count(*) FROM one_time_prekeys WHERE device_id = $2 AND consumed_at IS NULL) < $3
+            RETURNING id
+        )
+        INSERT INTO server_notices (user_id, device_id, kind)
+        SELECT $1, id, $4 FROM low
+        "#,
+        user_id,
+        device_id,
+        state.otk_low_watermark,
+        PREKEYS_LOW_NOTICE
+    )
+    .execute(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("prekey low signal: {}", e); ApiError::Internal })?;
+    Ok(())
+}
+
+/// Deletes the caller's unconsumed one-time prekeys with the given IDs, e.g. after the client lost
+/// their private halves. Consumed rows stay so the keys can't be uploaded again.
+pub async fn delete_one_time_prekeys(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<DeletePrekeysReq>,
+) -> Result<Json<DeletePrekeysResp>, ApiError> {
+    if req.key_ids.len() > MAX_OTKS_PER_DEVICE as usize {
+        return Err(ApiError::BadRequest(format!("at most {} key_ids per request", MAX_OTKS_PER_DEVICE)));
+    }
+
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM one_time_prekeys
+        WHERE user_id = $1 AND device_id = $2 AND key_id = ANY($3) AND consumed_at IS NULL
+        "#,
+        claims.sub,
+        device_id,
+        &req.key_ids
+    )
+    .execute(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("otk delete: {}", e); ApiError::Internal })?
+    .rows_affected();
+
+    Ok(Json(DeletePrekeysResp { deleted }))
+}
+
+/// Unconsumed one-time prekeys and signed-prekey age for each of the caller's active devices.
+pub async fn prekey_status(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<PrekeyStatusResp>, ApiError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT d.id AS device_id,
+               (SELECT count(*) FROM one_time_prekeys k
+                WHERE k.device_id = d.id AND k.consumed_at IS NULL) AS "unconsumed!",
+               s.key_id AS "signed_prekey_id?",
+               s.created_at AS "signed_prekey_created_at?"
+        FROM devices d
+        LEFT JOIN signed_prekeys s ON s.device_id = d.id AND s.superseded_at IS NULL
+        WHERE d.user_id = $1 AND d.revoked_at IS NULL
+        ORDER BY d.created_at
+        "#,
+        claims.sub
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("prekey status: {}", e); ApiError::Internal })?;
+
+    let stale_before = Utc::now() - Duration::days(SIGNED_PREKEY_MAX_AGE_DAYS);
+
+    Ok(Json(PrekeyStatusResp {
+        low_watermark: state.otk_low_watermark,
+        devices: rows.into_iter()
+            .map(|r| DevicePrekeyStatus {
+                device_id: r.device_id,
+                one_time_prekeys: r.unconsumed,
+                signed_prekey_id: r.signed_prekey_id,
+                signed_prekey_created_at: r.signed_prekey_created_at,
+                signed_prekey_stale: r.signed_prekey_created_at.is_none_or(|t| t < stale_before),
+            })
+            .collect(),
+    }))
 }
@@ -1,3 +1,8 @@
+use axum::{routing::{get, post}, Router, extract::{Path, Query, State}, Json};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::{state::AppState, errors::ApiError, auth::Claims};
+
 #[derive(Debug, Deserialize)]
 pub struct SendReq {
     pub to_user_id: Uuid,
@@ -12,9 +17,8 @@ pub struct SendResp { pub ok: bool }
 
 #[derive(Debug, Deserialize)]
 pub struct InboxQuery {
-    pub device_id: Uuid, 
-    // In real app, device_id should come from Auth Token claims to prevent spoofing.
-    // For V1, we'll verify it matches claims if claims has device_id.
+    // Optional: the device always comes from the token. If sent it must match.
+    pub device_id: Option<Uuid>,
 }
 
 #[derive(Debug, Serialize, sqlx::FromRow)]
@@ -31,19 +35,32 @@ pub struct InboxItem {
 
 // Note: InboxItem is used directly as the response array item. 
 
+/// Something the server (not a peer) wants the device to act on, e.g. `keys::PREKEYS_LOW_NOTICE`.
+#[derive(Debug, Serialize)]
+pub struct NoticeItem {
+    pub id: Uuid,
+    pub kind: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/messages/send", post(send))
         .route("/messages/inbox/:user_id", get(inbox))
+        .route("/messages/notices", get(notices))
 }
 
 pub async fn send(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    claims: Claims,
     Json(req): Json<SendReq>,
 ) -> Result<Json<SendResp>, ApiError> {
-    let claims = require_auth(&headers, &state)?;
     let sender_id = claims.sub;
+
+    // Sender device must be the one proven via identity-key challenge
+    if claims.device != Some(req.from_device_id) {
+        return Err(ApiError::Unauthorized);
+    }
     
     sqlx::query!(
         r#"
@@ -68,21 +85,21 @@ pub async fn send(
 
 pub async fn inbox(
     State(state): State<AppState>,
-    headers: HeaderMap,
+    claims: Claims,
     Path(user_id): Path<Uuid>,
     Query(q): Query<InboxQuery>,
 ) -> Result<Json<Vec<InboxItem>>, ApiError> {
-    let claims = require_auth(&headers, &state)?;
-    
     // Security check: You can only fetch inbox for YOUR user_id
     if claims.sub != user_id {
         return Err(ApiError::Unauthorized);
     }
     
     // For specific device inbox:
-    // Phase 2: Prefer device_id from token claims if present (secure binding)
-    // Fallback to query param for Phase 1 legacy tokens.
-    let target_device_id = claims.device.unwrap_or(q.device_id);
+    // The device claim is only set after an identity-key challenge, so it is trusted as-is.
+    let target_device_id = claims.device.ok_or(ApiError::Unauthorized)?;
+    if q.device_id.is_some_and(|d| d != target_device_id) {
+        return Err(ApiError::Unauthorized);
+    }
     // Provide simplistic "Fetch and Mark Delivered" logic (consume)
     
     let messages = sqlx::query_as!(
@@ -105,7 +122,38 @@ pub async fn inbox(
     )
     .fetch_all(&state.db)
     .await
-    .map_err(|e| { tracing::error!("inbox fetch: {}", e); ApiError::InternalServerError })?;
+    .map_err(|e| { tracing::error!("inbox fetch: {}", e); ApiError::Internal })?;
 
     Ok(Json(messages))
 }
+
+/// Pending server notices for the caller's device. Like the inbox, fetching consumes them.
+pub async fn notices(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<NoticeItem>>, ApiError> {
+    let device_id = claims.device.ok_or(ApiError::Unauthorized)?;
+
+    let mut notices = sqlx::query_as!(
+        NoticeItem,
+        r#"
+        DELETE FROM server_notices
+        WHERE id IN (
+            SELECT id FROM server_notices
+            WHERE user_id = $1 AND device_id = $2
+            ORDER BY created_at
+            LIMIT 100
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, kind, created_at
+        "#,
+        claims.sub,
+        device_id
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("notices fetch: {}", e); ApiError::Internal })?;
+
+    notices.sort_by_key(|n| n.created_at);
+    Ok(Json(notices))
+}
@@ -1,26 +1,34 @@
-use axum::Router;
+use axum::{extract::State, Json, Router};
 use crate::state::AppState;
 
-pub mod health;
+pub mod account;
 pub mod auth_routes;
 pub mod devices;
+pub mod discovery;
 pub mod keys;
 pub mod messages;
 pub mod attachments;
 pub mod safety;
+pub mod provisioning;
+pub mod users;
 
 pub async fn health() -> &'static str { "ok" }
 
+pub async fn jwks(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(state.keyring.jwks())
+}
+
+/// Everything under `/v1`; main.rs does the nesting.
 pub fn router() -> Router<AppState> {
-    let v1_api = Router::new()
+    Router::new()
         .merge(auth_routes::router())
+        .merge(account::router())
         .merge(devices::router())
+        .merge(discovery::router())
+        .merge(provisioning::router())
         .merge(keys::router())
         .merge(messages::router())
         .merge(attachments::router())
-        .merge(safety::router());
-
-    Router::new()
-        .route("/health", axum::routing::get(health))
-        .nest("/v1", v1_api)
+        .merge(safety::router())
+        .merge(users::router())
 }
@@ -0,0 +1,205 @@
+use axum::{routing::{get, post}, Router, extract::{Path, State}, Json};
+use chrono::{Duration, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Digest};
+use uuid::Uuid;
+use crate::{state::AppState, errors::ApiError, auth::Claims};
+use super::{auth_routes::{self, AuthResp}, devices::{self, ExistingDevice, RegisterDeviceReq}};
+
+// Linked-device flow:
+//   1. new device:      POST /provisioning/channel          -> address (shown as QR with its ephemeral pubkey)
+//   2. existing device: POST /provisioning/code             -> one-time code
+//   3. existing device: PUT  /provisioning/channel/:address -> blob sealed to the ephemeral key (contains code)
+//   4. new device:      GET  /provisioning/channel/:address -> blob (one-shot)
+//   5. new device:      POST /provisioning/redeem           -> registered under the same user_id
+// The server never sees the blob plaintext.
+
+const PROVISIONING_TTL_SECS: i64 = 600; // 10 minutes
+const MAX_BLOB_B64_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Serialize)]
+pub struct ChannelResp {
+    pub address: Uuid,
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CodeResp {
+    pub provisioning_code: String,
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PutBlobReq {
+    pub blob_b64: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlobResp {
+    pub blob_b64: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RedeemReq {
+    pub provisioning_code: String,
+    pub device_id: Uuid,
+    pub platform: String,
+    pub identity_key: String,
+    pub push_token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OkResp { pub ok: bool }
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/provisioning/channel", post(open_channel))
+        .route("/provisioning/channel/:address", get(fetch_blob).put(put_blob))
+        .route("/provisioning/code", post(issue_code))
+        .route("/provisioning/redeem", post(redeem))
+}
+
+fn hash_code(code: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(code.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+pub async fn open_channel(
+    State(state): State<AppState>,
+) -> Result<Json<ChannelResp>, ApiError> {
+    let address = Uuid::new_v4();
+    let expires_at = Utc::now() + Duration::seconds(PROVISIONING_TTL_SECS);
+
+    sqlx::query!(
+        r#"INSERT INTO provisioning_channels (address, expires_at) VALUES ($1, $2)"#,
+        address,
+        expires_at
+    )
+    .execute(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("provisioning channel: {}", e); ApiError::Internal })?;
+
+    Ok(Json(ChannelResp { address, expires_in: PROVISIONING_TTL_SECS }))
+}
+
+pub async fn issue_code(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<CodeResp>, ApiError> {
+    // Only an already-linked device may add another one.
+    let issuer = claims.device.ok_or(ApiError::Unauthorized)?;
+
+    let code = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+    let expires_at = Utc::now() + Duration::seconds(PROVISIONING_TTL_SECS);
+
+    sqlx::query!(
+        r#"
+        INSERT INTO provisioning_codes (code_hash, user_id, issued_by_device, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_code(&code),
+        claims.sub,
+        issuer,
+        expires_at
+    )
+    .execute(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("provisioning code: {}", e); ApiError::Internal })?;
+
+    Ok(Json(CodeResp { provisioning_code: code, expires_in: PROVISIONING_TTL_SECS }))
+}
+
+pub async fn put_blob(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(address): Path<Uuid>,
+    Json(req): Json<PutBlobReq>,
+) -> Result<Json<OkResp>, ApiError> {
+    claims.device.ok_or(ApiError::Unauthorized)?;
+    if req.blob_b64.is_empty() || req.blob_b64.len() > MAX_BLOB_B64_LEN {
+        return Err(ApiError::BadRequest("invalid provisioning blob".into()));
+    }
+
+    // First writer wins; a channel carries exactly one blob.
+    let updated = sqlx::query!(
+        r#"
+        UPDATE provisioning_channels SET blob_b64 = $1
+        WHERE address = $2 AND blob_b64 IS NULL AND expires_at > now()
+        "#,
+        req.blob_b64,
+        address
+    )
+    .execute(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("provisioning put: {}", e); ApiError::Internal })?;
+
+    if updated.rows_affected() == 0 {
+        return Err(ApiError::NotFound("Provisioning channel not found".into()));
+    }
+
+    Ok(Json(OkResp { ok: true }))
+}
+
+pub async fn fetch_blob(
+    State(state): State<AppState>,
+    Path(address): Path<Uuid>,
+) -> Result<Json<BlobResp>, ApiError> {
+    // The address is the capability; the blob is deleted on first read.
+    let channel = sqlx::query!(
+        r#"
+        DELETE FROM provisioning_channels
+        WHERE address = $1 AND blob_b64 IS NOT NULL AND expires_at > now()
+        RETURNING blob_b64 AS "blob_b64!"
+        "#,
+        address
+    )
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("provisioning fetch: {}", e); ApiError::Internal })?;
+
+    let channel = channel.ok_or(ApiError::NotFound("Nothing provisioned yet".into()))?;
+
+    Ok(Json(BlobResp { blob_b64: channel.blob_b64 }))
+}
+
+pub async fn redeem(
+    State(state): State<AppState>,
+    Json(req): Json<RedeemReq>,
+) -> Result<Json<AuthResp>, ApiError> {
+    let device = RegisterDeviceReq {
+        device_id: req.device_id,
+        platform: req.platform,
+        identity_key: req.identity_key,
+        push_token: req.push_token,
+    };
+    devices::validate_device_req(&device)?;
+
+    // The code is only spent if the device is actually stored; any failure rolls both back.
+    let mut tx = state.db.begin().await
+        .map_err(|e| { tracing::error!("db begin: {}", e); ApiError::Internal })?;
+
+    let code = sqlx::query!(
+        r#"
+        UPDATE provisioning_codes SET used_at = now()
+        WHERE code_hash = $1 AND used_at IS NULL AND expires_at > now()
+        RETURNING user_id
+        "#,
+        hash_code(&req.provisioning_code)
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| { tracing::error!("provisioning redeem: {}", e); ApiError::Internal })?;
+
+    let user_id = code.ok_or(ApiError::Unauthorized)?.user_id;
+
+    devices::store_device(&mut tx, state.max_devices_per_user, user_id, &device, ExistingDevice::Reject).await?;
+
+    tx.commit().await
+        .map_err(|e| { tracing::error!("db commit: {}", e); ApiError::Internal })?;
+
+    // Account-level token; the new device binds itself via /auth/device/challenge once its keys are up.
+    let resp = auth_routes::issue_session(&state, user_id, None).await?;
+    Ok(Json(resp))
+}
@@ -1,7 +1,7 @@
-use axum::{routing::post, Router, extract::State, Json, http::HeaderMap};
+use axum::{routing::post, Router, extract::State, Json};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use crate::{state::AppState, errors::ApiError, auth::require_auth};
+use crate::{state::AppState, errors::ApiError, auth::Claims};
 
 #[derive(Debug, Deserialize)]
 pub struct BlockReq {
@@ -22,12 +22,10 @@ pub fn router() -> Router<AppState> {
 }
 
 pub async fn block_user(
-    State(state): State<AppState>,
-    headers: HeaderMap,
+    State(_state): State<AppState>,
+    _claims: Claims,
     Json(req): Json<BlockReq>,
 ) -> Result<Json<serde_json::Value>, ApiError> {
-    let _claims = require_auth(&headers, &state)?;
-    
     // DEV STUB: In Phase 2/3 we insert into 'blocks' table
     tracing::info!("User blocking: {:?}", req.blocked_user_id);
     
@@ -35,12 +33,10 @@ pub async fn block_user(
 }
 
 pub async fn submit_report(
-    State(state): State<AppState>,
-    headers: HeaderMap,
+    State(_state): State<AppState>,
+    _claims: Claims,
     Json(req): Json<ReportReq>,
 ) -> Result<Json<serde_json::Value>, ApiError> {
-    let _claims = require_auth(&headers, &state)?;
-    
     // DEV STUB: In Phase 2/3 we insert into 'reports' table 
     tracing::info!("Abuse report submitted: {:?}", req.reported_user_id);
     
@@ -0,0 +1,313 @@
+use axum::{routing::{get, put}, Router, extract::{Path, Query, State}, Json};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::{state::AppState, errors::{ApiError, unique_violation}, auth::Claims, reglock};
+
+const USERNAME_MIN_LEN: usize = 3;
+const USERNAME_MAX_LEN: usize = 32;
+const DISCRIMINATOR_ATTEMPTS: usize = 10;
+const LOOKUPS_PER_HOUR: i32 = 100;
+
+/// Names nobody may claim, so they can't be used to impersonate the service.
+const RESERVED_USERNAMES: &[&str] = &[
+    "admin", "administrator", "help", "moderator", "official", "root",
+    "security", "speakeasy", "staff", "support", "system",
+];
+
+#[derive(Debug, Deserialize)]
+pub struct PresenceSettingReq {
+    pub share_presence: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PresenceResp {
+    pub user_id: Uuid,
+    pub status: &'static str, // "recently" (< 1h), "this_week", "long_ago"
+}
+
+#[derive(Debug, Serialize)]
+pub struct OkResp { pub ok: bool }
+
+#[derive(Debug, Deserialize)]
+pub struct SetUsernameReq {
+    pub username: String, // name part only; the server picks the discriminator
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsernameResp {
+    pub username: String, // "name.1234"
+}
+
+#[derive(Deserialize)]
+pub struct RegistrationLockReq {
+    pub token: String, // hex, derived from the PIN on the client
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LookupQuery {
+    pub username: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LookupResp {
+    pub user_id: Uuid,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/users/me/presence", put(set_presence_sharing))
+        .route("/users/me/presence/viewers/:user_id", put(allow_presence_viewer).delete(remove_presence_viewer))
+        .route("/users/me/username", put(set_username).delete(delete_username))
+        .route("/users/me/registration-lock", put(set_registration_lock).delete(clear_registration_lock))
+        .route("/users/lookup", get(lookup_username))
+        .route("/users/:user_id/presence", get(get_presence))
+}
+
+/// 3-32 chars of `[a-z0-9_]`, starting with a letter. Case is kept for display only.
+fn validate_username(name: &str) -> Result<(), ApiError> {
+    let lower = name.to_ascii_lowercase();
+    let valid = (USERNAME_MIN_LEN..=USERNAME_MAX_LEN).contains(&name.len())
+        && lower.starts_with(|c: char| c.is_ascii_lowercase())
+        && lower.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid {
+        return Err(ApiError::BadRequest("username must be 3-32 letters, digits or _ and start with a letter".into()));
+    }
+    if RESERVED_USERNAMES.contains(&lower.as_str()) {
+        return Err(ApiError::Conflict("username_reserved"));
+    }
+    Ok(())
+}
+
+pub async fn set_username(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(req): Json<SetUsernameReq>,
+) -> Result<Json<UsernameResp>, ApiError> {
+    let name = req.username.trim();
+    validate_username(name)?;
+
+    // Random discriminator; retry on collision with an existing `name.NNNN`.
+    for _ in 0..DISCRIMINATOR_ATTEMPTS {
+        let username = format!("{}.{:04}", name, rand::thread_rng().gen_range(1..10_000));
+
+        let res = sqlx::query!(
+            r#"UPDATE users SET username = $1, updated_at = now() WHERE id = $2"#,
+            username,
+            claims.sub
+        )
+        .execute(&state.db)
+        .await;
+
+        match res {
+            Ok(_) => return Ok(Json(UsernameResp { username })),
+            Err(e) if unique_violation(&e).as_deref() == Some("users_username_key") => continue,
+            Err(e) => { tracing::error!("set username: {}", e); return Err(ApiError::Internal); }
+        }
+    }
+
+    Err(ApiError::Conflict("username_unavailable"))
+}
+
+pub async fn delete_username(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<OkResp>, ApiError> {
+    sqlx::query!(
+        r#"UPDATE users SET username = NULL, updated_at = now() WHERE id = $1"#,
+        claims.sub
+    )
+    .execute(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("delete username: {}", e); ApiError::Internal })?;
+
+    Ok(Json(OkResp { ok: true }))
+}
+
+/// Exact `name.discriminator` match only, so names can't be enumerated by prefix.
+pub async fn lookup_username(
+    State(state): State<AppState>,
+    claims: Claims,
+    Query(q): Query<LookupQuery>,
+) -> Result<Json<LookupResp>, ApiError> {
+    state.rate_limiter.check("username_lookup", &claims.sub.to_string(), LOOKUPS_PER_HOUR, 3600).await?;
+
+    let user = sqlx::query!(
+        r#"
+        SELECT u.id FROM users u
+        WHERE lower(u.username) = lower($1)
+          AND u.status = 'active'
+          AND NOT EXISTS(
+              SELECT 1 FROM blocked_users b
+              WHERE b.blocker_user_id = u.id AND b.blocked_user_id = $2
+          )
+        "#,
+        q.username.trim(),
+        claims.sub
+    )
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("username lookup: {}", e); ApiError::Internal })?;
+
+    let user = user.ok_or(ApiError::NotFound("User not found".into()))?;
+    Ok(Json(LookupResp { user_id: user.id }))
+}
+
+pub async fn set_presence_sharing(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(req): Json<PresenceSettingReq>,
+) -> Result<Json<OkResp>, ApiError> {
+    sqlx::query!(
+        r#"UPDATE users SET share_presence = $1, updated_at = now() WHERE id = $2"#,
+        req.share_presence,
+        claims.sub
+    )
+    .execute(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("presence setting: {}", e); ApiError::Internal })?;
+
+    Ok(Json(OkResp { ok: true }))
+}
+
+/// Lets `user_id` see the caller's presence (once sharing is on).
+pub async fn allow_presence_viewer(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<OkResp>, ApiError> {
+    if user_id == claims.sub {
+        return Err(ApiError::BadRequest("cannot add yourself".into()));
+    }
+
+    let added = sqlx::query!(
+        r#"
+        INSERT INTO presence_viewers (owner_user_id, viewer_user_id)
+        SELECT $1, id FROM users WHERE id = $2
+        ON CONFLICT DO NOTHING
+        "#,
+        claims.sub,
+        user_id
+    )
+    .execute(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("presence viewer add: {}", e); ApiError::Internal })?;
+
+    if added.rows_affected() == 0 {
+        // Either already allowed or no such user; only the latter is an error.
+        let exists = sqlx::query!(r#"SELECT EXISTS(SELECT 1 FROM users WHERE id = $1) AS "exists!""#, user_id)
+            .fetch_one(&state.db)
+            .await
+            .map_err(|e| { tracing::error!("presence viewer lookup: {}", e); ApiError::Internal })?;
+        if !exists.exists {
+            return Err(ApiError::NotFound("User not found".into()));
+        }
+    }
+
+    Ok(Json(OkResp { ok: true }))
+}
+
+pub async fn remove_presence_viewer(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<OkResp>, ApiError> {
+    sqlx::query!(
+        r#"DELETE FROM presence_viewers WHERE owner_user_id = $1 AND viewer_user_id = $2"#,
+        claims.sub,
+        user_id
+    )
+    .execute(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("presence viewer remove: {}", e); ApiError::Internal })?;
+
+    Ok(Json(OkResp { ok: true }))
+}
+
+/// Coarse "last active" across the user's devices, shown only to viewers the target put on
+/// their presence allow-list. Users who haven't opted in, viewers not on the list, and users
+/// who blocked the requester look the same as unknown users.
+pub async fn get_presence(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<PresenceResp>, ApiError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT MAX(d.last_seen_at) FROM devices d WHERE d.user_id = u.id AND d.revoked_at IS NULL) AS last_seen_at,
+            EXISTS(
+                SELECT 1 FROM blocked_users b
+                WHERE b.blocker_user_id = u.id AND b.blocked_user_id = $2
+            ) AS "blocked!",
+            EXISTS(
+                SELECT 1 FROM presence_viewers v
+                WHERE v.owner_user_id = u.id AND v.viewer_user_id = $2
+            ) AS "allowed!"
+        FROM users u
+        WHERE u.id = $1 AND u.share_presence = true AND u.status = 'active'
+        "#,
+        user_id,
+        claims.sub
+    )
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("presence lookup: {}", e); ApiError::Internal })?;
+
+    let row = match row {
+        Some(r) if r.allowed && !r.blocked => r,
+        _ => return Err(ApiError::NotFound("Presence not available".into())),
+    };
+
+    let age = row.last_seen_at.map(|t| chrono::Utc::now() - t);
+    let status = match age {
+        Some(a) if a < chrono::Duration::hours(1) => "recently",
+        Some(a) if a < chrono::Duration::days(7) => "this_week",
+        _ => "long_ago",
+    };
+
+    Ok(Json(PresenceResp { user_id, status }))
+}
+
+pub async fn set_registration_lock(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(req): Json<RegistrationLockReq>,
+) -> Result<Json<OkResp>, ApiError> {
+    let token_ok = req.token.len() >= 64 && req.token.chars().all(|c| c.is_ascii_hexdigit());
+    if !token_ok {
+        return Err(ApiError::BadRequest("token must be at least 32 bytes of hex".into()));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE users SET reglock_hash = $1, reglock_enabled_at = now(), updated_at = now()
+        WHERE id = $2
+        "#,
+        reglock::hash_token(&req.token),
+        claims.sub
+    )
+    .execute(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("set reglock: {}", e); ApiError::Internal })?;
+
+    Ok(Json(OkResp { ok: true }))
+}
+
+pub async fn clear_registration_lock(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<OkResp>, ApiError> {
+    sqlx::query!(
+        r#"
+        UPDATE users SET reglock_hash = NULL, reglock_enabled_at = NULL, updated_at = now()
+        WHERE id = $1
+        "#,
+        claims.sub
+    )
+    .execute(&state.db)
+    .await
+    .map_err(|e| { tracing::error!("clear reglock: {}", e); ApiError::Internal })?;
+
+    Ok(Json(OkResp { ok: true }))
+}
@@ -1,20 +1,45 @@
 use sqlx::{PgPool, postgres::PgPoolOptions};
-use std::env;
+use std::{env, str::FromStr, sync::Arc};
 use s3::bucket::Bucket;
 use s3::creds::Credentials;
 use s3::region::Region;
+use crate::challenge::{self, RegistrationChallenge};
+use crate::identifiers::IdentifierHasher;
+use crate::keyring::Keyring;
+use crate::otp::{self, OtpSender};
+use crate::presence::LastSeenThrottle;
+use crate::rate_limit::RateLimiter;
+use crate::revocation::RevocationCache;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: PgPool,
-    pub jwt_secret: String,
+    pub keyring: Arc<Keyring>,
+    pub identifiers: Arc<IdentifierHasher>,
     pub bucket: Bucket,
+    pub otp_sender: Arc<dyn OtpSender>,
+    pub registration_challenge: Arc<dyn RegistrationChallenge>,
+    pub revocation: Arc<RevocationCache>,
+    pub last_seen: Arc<LastSeenThrottle>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub trust_proxy: bool, // read the client IP from the last X-Forwarded-For hop (single proxy in front)
+    pub max_devices_per_user: i64,
+    pub stale_device_days: i64,
+    pub otk_low_watermark: i64,
+    pub otk_retention_days: i64,
+    pub signed_prekey_grace_days: i64,
+}
+
+/// Reads an optional numeric/bool setting, falling back to `default` if unset or unparsable.
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
 }
 
 impl AppState {
     pub async fn new_from_env() -> anyhow::Result<Self> {
         let database_url = env::var("DATABASE_URL")?;
-        let jwt_secret = env::var("JWT_SECRET")?;
+        let keyring = Arc::new(Keyring::from_env()?);
+        let identifiers = Arc::new(IdentifierHasher::from_env()?);
         
         // S3 Config
         let s3_endpoint = env::var("S3_ENDPOINT").unwrap_or("http://localhost:9000".to_string());
@@ -28,11 +53,38 @@ impl AppState {
         
         let bucket = Bucket::new(&s3_bucket_name, region, credentials)?.with_path_style();
 
+        let otp_sender = otp::sender_from_env()?;
+        let registration_challenge = challenge::from_env()?;
+        let max_devices_per_user = env_or("MAX_DEVICES_PER_USER", 5);
+        let stale_device_days = env_or("STALE_DEVICE_DAYS", 90); // 0 disables the sweep
+        let trust_proxy = env_or("TRUST_PROXY", false);
+        let otk_low_watermark = env_or("OTK_LOW_WATERMARK", 20);
+        let otk_retention_days = env_or("OTK_RETENTION_DAYS", 30);
+        let signed_prekey_grace_days = env_or("SIGNED_PREKEY_GRACE_DAYS", 14);
+
         let db = PgPoolOptions::new()
             .max_connections(10)
             .connect(&database_url)
             .await?;
 
-        Ok(Self { db, jwt_secret, bucket })
+        let rate_limiter = Arc::new(RateLimiter::from_env(&db)?);
+
+        Ok(Self {
+            db,
+            keyring,
+            identifiers,
+            bucket,
+            otp_sender,
+            registration_challenge,
+            revocation: Arc::new(RevocationCache::default()),
+            last_seen: Arc::new(LastSeenThrottle::default()),
+            rate_limiter,
+            trust_proxy,
+            max_devices_per_user,
+            stale_device_days,
+            otk_low_watermark,
+            otk_retention_days,
+            signed_prekey_grace_days,
+        })
     }
 }
@@ -0,0 +1,126 @@
+use chrono::{Duration, Utc};
+use rand::Rng;
+use sha2::{Sha256, Digest};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::errors::ApiError;
+
+pub const ACCESS_TOKEN_TTL_SECS: i64 = 900; // 15 minutes
+pub const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+pub struct RefreshOwner {
+    pub family_id: Uuid,
+    pub user_id: Uuid,
+    pub device_id: Option<Uuid>,
+}
+
+fn generate_token() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+fn hash_token(token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(token.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Stores a new refresh token in `family_id` and returns the plaintext token.
+pub async fn issue_refresh(
+    db: &PgPool,
+    family_id: Uuid,
+    user_id: Uuid,
+    device_id: Option<Uuid>,
+) -> Result<String, ApiError> {
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::days(REFRESH_TOKEN_TTL_DAYS);
+
+    sqlx::query!(
+        r#"
+        INSERT INTO refresh_tokens (family_id, user_id, device_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        family_id,
+        user_id,
+        device_id,
+        hash_token(&token),
+        expires_at
+    )
+    .execute(db)
+    .await
+    .map_err(|e| { tracing::error!("refresh insert: {}", e); ApiError::Internal })?;
+
+    Ok(token)
+}
+
+/// Marks a refresh token as used and returns who it belongs to.
+/// Presenting an already-used or revoked token is treated as theft and revokes the whole family.
+pub async fn consume_refresh(db: &PgPool, token: &str) -> Result<RefreshOwner, ApiError> {
+    let token_hash = hash_token(token);
+
+    let row = sqlx::query!(
+        r#"
+        SELECT id, family_id, user_id, device_id, expires_at, used_at, revoked_at
+        FROM refresh_tokens
+        WHERE token_hash = $1
+        "#,
+        token_hash
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| { tracing::error!("refresh lookup: {}", e); ApiError::Internal })?;
+
+    let row = row.ok_or(ApiError::Unauthorized)?;
+
+    if row.used_at.is_some() || row.revoked_at.is_some() {
+        tracing::warn!("refresh token reuse detected, revoking family {}", row.family_id);
+        revoke_family(db, row.family_id).await?;
+        return Err(ApiError::Unauthorized);
+    }
+    if row.expires_at <= Utc::now() {
+        return Err(ApiError::Unauthorized);
+    }
+
+    // Guard against two concurrent refreshes with the same token.
+    let marked = sqlx::query!(
+        r#"UPDATE refresh_tokens SET used_at = now() WHERE id = $1 AND used_at IS NULL"#,
+        row.id
+    )
+    .execute(db)
+    .await
+    .map_err(|e| { tracing::error!("refresh mark used: {}", e); ApiError::Internal })?;
+
+    if marked.rows_affected() == 0 {
+        tracing::warn!("refresh token raced, revoking family {}", row.family_id);
+        revoke_family(db, row.family_id).await?;
+        return Err(ApiError::Unauthorized);
+    }
+
+    Ok(RefreshOwner { family_id: row.family_id, user_id: row.user_id, device_id: row.device_id })
+}
+
+/// Revokes the family the given token belongs to. Unknown tokens are ignored.
+pub async fn revoke_by_token(db: &PgPool, token: &str) -> Result<(), ApiError> {
+    sqlx::query!(
+        r#"
+        UPDATE refresh_tokens SET revoked_at = now()
+        WHERE revoked_at IS NULL
+          AND family_id = (SELECT family_id FROM refresh_tokens WHERE token_hash = $1)
+        "#,
+        hash_token(token)
+    )
+    .execute(db)
+    .await
+    .map_err(|e| { tracing::error!("refresh revoke: {}", e); ApiError::Internal })?;
+    Ok(())
+}
+
+pub async fn revoke_family(db: &PgPool, family_id: Uuid) -> Result<(), ApiError> {
+    sqlx::query!(
+        r#"UPDATE refresh_tokens SET revoked_at = now() WHERE family_id = $1 AND revoked_at IS NULL"#,
+        family_id
+    )
+    .execute(db)
+    .await
+    .map_err(|e| { tracing::error!("refresh revoke family: {}", e); ApiError::Internal })?;
+    Ok(())
+}
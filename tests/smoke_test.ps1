@@ -9,7 +9,7 @@ function Test-Endpoint($name, $path, $method = "GET", $body = $null) {
             Method = $method
             ContentType = "application/json"
         }
-        if ($body) { $params.Body = ($body | ConvertTo-Json) }
+        if ($body) { $params.Body = ($body | ConvertTo-Json -Depth 5) }
         
         $resp = Invoke-RestMethod @params
         Write-Host "Success!" -ForegroundColor Green
@@ -42,8 +42,7 @@ if ($reg) {
         device_id = [guid]::NewGuid().ToString()
         identity_key_ed25519_b64 = "base64_id_key"
         static_x25519_b64 = "base64_static_key"
-        signed_prekey_x25519_b64 = "base64_signed_prekey"
-        signed_prekey_signature_b64 = "base64_sig"
-        one_time_prekeys_b64 = @("otk1")
+        signed_prekey = @{ key_id = 1; public_key = "base64_signed_prekey"; signature = "base64_sig" }
+        one_time_prekeys = @(@{ key_id = 1; public_key = "otk1" })
     }
 }